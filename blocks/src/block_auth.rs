@@ -0,0 +1,174 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use builder_block::proto::auth::{
+    auth_service_client::AuthServiceClient, GenerateAuthChallengeRequest,
+    GenerateAuthTokensRequest, RefreshAccessTokenRequest, Role, Token,
+};
+use log::{info, warn};
+use solana_sdk::signature::{Keypair, Signer};
+use tokio::time::sleep;
+use tonic::{metadata::MetadataValue, service::Interceptor, transport::Channel, Request, Status};
+
+use crate::block_stats::EngineStats;
+
+/// Tokens are renewed once they are this close to expiring.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+/// First and longest wait before retrying a failed refresh; both well under
+/// [`TOKEN_REFRESH_MARGIN`].
+const MIN_REFRESH_RETRY: Duration = Duration::from_secs(1);
+const MAX_REFRESH_RETRY: Duration = Duration::from_secs(30);
+
+/// Adds the current access token to every request made through a client.
+#[derive(Clone)]
+pub struct AuthInterceptor {
+    access_token: Arc<RwLock<String>>,
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let bearer = format!("Bearer {}", self.access_token.read().unwrap());
+        let value = MetadataValue::try_from(bearer)
+            .map_err(|_| Status::unauthenticated("Invalid access token"))?;
+        request.metadata_mut().insert("authorization", value);
+        Ok(request)
+    }
+}
+
+/// Relayer credentials for the block engine, obtained by signing the auth
+/// service's challenge with the relayer keypair.
+pub struct AuthSession {
+    auth_client: AuthServiceClient<Channel>,
+    keypair: Arc<Keypair>,
+    access_token: Arc<RwLock<String>>,
+    access_expiry: Option<SystemTime>,
+    refresh_token: Token,
+}
+
+impl AuthSession {
+    pub async fn new(channel: Channel, keypair: Arc<Keypair>) -> Result<Self, String> {
+        let mut auth_client = AuthServiceClient::new(channel);
+        let (access_token, refresh_token) =
+            Self::generate_tokens(&mut auth_client, &keypair).await?;
+        Ok(AuthSession {
+            auth_client,
+            keypair,
+            access_expiry: expiry(&access_token),
+            access_token: Arc::new(RwLock::new(access_token.value)),
+            refresh_token,
+        })
+    }
+
+    pub fn interceptor(&self) -> AuthInterceptor {
+        AuthInterceptor {
+            access_token: self.access_token.clone(),
+        }
+    }
+
+    /// Keeps the tokens fresh, checking every `check_interval`. A failed
+    /// refresh is retried with backoff while the current access token is
+    /// still valid; once it has expired the error is returned.
+    pub async fn keep_fresh(mut self, stats: Arc<EngineStats>, check_interval: Duration) -> String {
+        let mut next_check = check_interval;
+        let mut retry = MIN_REFRESH_RETRY;
+        loop {
+            sleep(next_check).await;
+            match self.maybe_refresh(&stats).await {
+                Ok(()) => {
+                    next_check = check_interval;
+                    retry = MIN_REFRESH_RETRY;
+                }
+                Err(e) if expires_before(self.access_expiry, SystemTime::now()) => {
+                    return format!("Access token expired and could not be refreshed: {e}");
+                }
+                Err(e) => {
+                    warn!("Failed to refresh block engine auth, retrying in {retry:?}: {e}");
+                    next_check = retry;
+                    retry = (retry * 2).min(MAX_REFRESH_RETRY);
+                }
+            }
+        }
+    }
+
+    /// Renews the access token if it is about to expire, authenticating from
+    /// scratch when the refresh token is about to expire too.
+    pub async fn maybe_refresh(&mut self, stats: &EngineStats) -> Result<(), String> {
+        let refresh_at = SystemTime::now() + TOKEN_REFRESH_MARGIN;
+        if !expires_before(self.access_expiry, refresh_at) {
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        let access_token = if expires_before(expiry(&self.refresh_token), refresh_at) {
+            info!("Refresh token expiring, re-authenticating with block engine");
+            let (access_token, refresh_token) =
+                Self::generate_tokens(&mut self.auth_client, &self.keypair).await?;
+            self.refresh_token = refresh_token;
+            access_token
+        } else {
+            self.auth_client
+                .refresh_access_token(RefreshAccessTokenRequest {
+                    refresh_token: self.refresh_token.value.clone(),
+                })
+                .await
+                .map_err(|e| e.to_string())?
+                .into_inner()
+                .access_token
+                .ok_or_else(|| "Auth service returned no access token".to_string())?
+        };
+        self.access_expiry = expiry(&access_token);
+        *self.access_token.write().unwrap() = access_token.value;
+
+        stats.auth_refresh_count.add(1);
+        stats
+            .refresh_auth_elapsed_us
+            .record(start.elapsed().as_micros() as u64);
+        Ok(())
+    }
+
+    async fn generate_tokens(
+        auth_client: &mut AuthServiceClient<Channel>,
+        keypair: &Keypair,
+    ) -> Result<(Token, Token), String> {
+        let challenge = auth_client
+            .generate_auth_challenge(GenerateAuthChallengeRequest {
+                role: Role::Relayer as i32,
+                pubkey: keypair.pubkey().as_ref().to_vec(),
+            })
+            .await
+            .map_err(|e| e.to_string())?
+            .into_inner()
+            .challenge;
+
+        let challenge = format!("{}-{}", keypair.pubkey(), challenge);
+        let signed_challenge = keypair.sign_message(challenge.as_bytes()).as_ref().to_vec();
+        let tokens = auth_client
+            .generate_auth_tokens(GenerateAuthTokensRequest {
+                challenge,
+                client_pubkey: keypair.pubkey().as_ref().to_vec(),
+                signed_challenge,
+            })
+            .await
+            .map_err(|e| e.to_string())?
+            .into_inner();
+
+        match (tokens.access_token, tokens.refresh_token) {
+            (Some(access_token), Some(refresh_token)) => Ok((access_token, refresh_token)),
+            _ => Err("Auth service returned incomplete tokens".to_string()),
+        }
+    }
+}
+
+/// Tokens without an expiry are treated as never expiring.
+fn expiry(token: &Token) -> Option<SystemTime> {
+    token
+        .expires_at_utc
+        .clone()
+        .and_then(|ts| SystemTime::try_from(ts).ok())
+}
+
+fn expires_before(expiry: Option<SystemTime>, time: SystemTime) -> bool {
+    expiry.is_some_and(|expiry| expiry <= time)
+}
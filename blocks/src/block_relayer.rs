@@ -1,33 +1,142 @@
 use std::{
-    collections::HashSet,
     str::FromStr,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+        Arc,
     },
-    thread::{Builder, JoinHandle},
+    thread::JoinHandle,
     time::{Duration, Instant, SystemTime},
 };
 
-use cached::{Cached, TimedCache};
+use builder_block::proto::{
+    block_engine::{
+        block_engine_relayer_client::BlockEngineRelayerClient, packet_batch_update::Msg,
+        AccountsOfInterestRequest, AccountsOfInterestUpdate, ExpiringPacketBatch,
+        PacketBatchUpdate, ProgramsOfInterestRequest, ProgramsOfInterestUpdate,
+        StartExpiringPacketStreamResponse,
+    },
+    shared::{Header, Heartbeat},
+};
 use dashmap::DashMap;
 use log::{error, info, warn};
 use prost_types::Timestamp;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair};
 use tokio::{
     runtime::Runtime,
     select,
     sync::mpsc::{channel, Receiver, Sender},
+    task::JoinHandle as TaskHandle,
     time::{interval, sleep},
 };
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{
-    transport::{Channel, Endpoint},
-    Response, Status, Streaming,
+    transport::{Channel, ClientTlsConfig, Endpoint},
+    Streaming,
 };
 
+use crate::{
+    block_auth::AuthSession,
+    block_stats::EngineStats,
+    metrics::MetricsSink,
+    packet::{account_keys, uses_address_lookups, Packet, PacketBatch},
+    supervisor::spawn_supervised,
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+/// How long the engine may go without sending a heartbeat before the stream
+/// is considered dead.
+const ENGINE_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+const AUTH_REFRESH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How long the engine may hold a forwarded packet before discarding it.
+const PACKET_EXPIRY_MS: u32 = 200;
+const ENGINE_STREAM_BUFFER: usize = 1_000;
+/// How long an account or program stays of interest after the engine last
+/// sent it.
+const INTEREST_TTL: Duration = Duration::from_secs(300);
+
 pub struct EngineConfig {
     pub engine_url: String,
     pub auth_service_url: String,
+    /// Identity the relayer authenticates to the block engine with.
+    pub keypair: Arc<Keypair>,
+    /// TLS settings for the auth service and block engine channels.
+    pub tls: Option<ClientTlsConfig>,
+}
+
+/// Accounts and programs the block engine has asked for, each with the time
+/// it was last sent. Only packets touching one of them are forwarded.
+///
+/// Until the engine has sent both its first accounts and programs update,
+/// nothing is known to be uninteresting, so every packet is forwarded.
+#[derive(Default)]
+pub struct InterestSets {
+    accounts: DashMap<Pubkey, Instant>,
+    programs: DashMap<Pubkey, Instant>,
+    accounts_received: AtomicBool,
+    programs_received: AtomicBool,
+}
+
+impl InterestSets {
+    fn update_accounts(&self, keys: &[String]) -> u64 {
+        self.accounts_received.store(true, Ordering::Relaxed);
+        Self::update(&self.accounts, keys)
+    }
+
+    fn update_programs(&self, keys: &[String]) -> u64 {
+        self.programs_received.store(true, Ordering::Relaxed);
+        Self::update(&self.programs, keys)
+    }
+
+    fn update(set: &DashMap<Pubkey, Instant>, keys: &[String]) -> u64 {
+        let now = Instant::now();
+        let mut received = 0;
+        for key in keys {
+            match Pubkey::from_str(key) {
+                Ok(pubkey) => {
+                    set.insert(pubkey, now);
+                    received += 1;
+                }
+                Err(_) => warn!("Ignoring invalid pubkey of interest: {key}"),
+            }
+        }
+        received
+    }
+
+    /// Forgets entries the engine hasn't sent within [`INTEREST_TTL`].
+    fn prune(&self) {
+        self.accounts
+            .retain(|_, last_seen| last_seen.elapsed() < INTEREST_TTL);
+        self.programs
+            .retain(|_, last_seen| last_seen.elapsed() < INTEREST_TTL);
+    }
+
+    /// Whether any of the packet's static account keys, program ids
+    /// included, is of interest. Packets loading accounts from address
+    /// lookup tables are always forwarded, since the accounts they resolve
+    /// to are unknown here.
+    pub fn is_of_interest(&self, packet: &Packet) -> bool {
+        if !self.accounts_received.load(Ordering::Relaxed)
+            || !self.programs_received.load(Ordering::Relaxed)
+            || uses_address_lookups(packet.data()) == Some(true)
+        {
+            return true;
+        }
+        account_keys(packet.data()).is_some_and(|mut keys| {
+            keys.any(|key| self.accounts.contains_key(&key) || self.programs.contains_key(&key))
+        })
+    }
+}
+
+/// Open streams to an authenticated block engine.
+struct EngineConnection {
+    /// Task keeping the auth tokens fresh; it only finishes, with the
+    /// reason, once the access token has expired without being renewed.
+    auth_refresh: TaskHandle<String>,
+    update_sender: Sender<PacketBatchUpdate>,
+    packet_stream: Streaming<StartExpiringPacketStreamResponse>,
+    aoi_stream: Streaming<AccountsOfInterestUpdate>,
+    poi_stream: Streaming<ProgramsOfInterestUpdate>,
 }
 
 pub struct EngineRelayerHandler {
@@ -35,67 +144,329 @@ pub struct EngineRelayerHandler {
 }
 
 impl EngineRelayerHandler {
+    pub const ENGINE_PACKET_QUEUE_CAPACITY: usize = 5_000;
+
     pub fn new(
         engine_config: Option<EngineConfig>,
-        mut engine_receiver: Receiver<Vec<u8>>,
+        mut engine_receiver: Receiver<PacketBatch>,
+        metrics_sink: Arc<dyn MetricsSink>,
         exit: Arc<AtomicBool>,
     ) -> EngineRelayerHandler {
         let engine_forwarder = engine_config.map(|config| {
-            Builder::new()
-                .name("engine_relayer_handler_thread".into())
-                .spawn(move || {
+            spawn_supervised(
+                "engine_relayer_handler_thread".into(),
+                exit.clone(),
+                move || {
                     let rt = Runtime::new().unwrap();
-                    rt.block_on(async move {
+                    let interest = InterestSets::default();
+                    let stats = Arc::new(EngineStats::default());
+                    rt.block_on(async {
                         while !exit.load(Ordering::Relaxed) {
                             let result = Self::auth_and_connect(
-                                &config.engine_url,
-                                &config.auth_service_url,
+                                &config,
                                 &mut engine_receiver,
+                                &interest,
+                                &stats,
+                                metrics_sink.as_ref(),
                                 &exit,
                             )
                             .await;
 
-                            if let Err(e) = result {
-                                error!("Error connecting: {:?}", e);
-                                sleep(Duration::from_secs(2)).await;
+                            match result {
+                                Ok(()) => break,
+                                Err(e) => {
+                                    error!("Error connecting: {:?}", e);
+                                    sleep(Duration::from_secs(2)).await;
+                                }
                             }
                         }
                     });
-                })
-                .unwrap()
+                },
+            )
         });
 
         EngineRelayerHandler { engine_forwarder }
     }
 
+    /// Waits for the handler to finish; it does so once the relayer exits or
+    /// the engine packet channel is closed.
     pub fn join(self) {
         if let Some(forwarder) = self.engine_forwarder {
-            forwarder.join().unwrap()
+            if forwarder.join().is_err() {
+                error!("Engine relayer handler thread panicked");
+            }
         }
     }
 
+    /// Authenticates with the block engine and streams packets to it until
+    /// the stream breaks, the engine packet channel closes or the relayer
+    /// exits. Any error means the caller should reconnect; on success the
+    /// streams are dropped, closing them.
     async fn auth_and_connect(
-        engine_url: &str,
-        auth_service_url: &str,
-        engine_receiver: &mut Receiver<Vec<u8>>,
+        config: &EngineConfig,
+        engine_receiver: &mut Receiver<PacketBatch>,
+        interest: &InterestSets,
+        stats: &Arc<EngineStats>,
+        metrics_sink: &dyn MetricsSink,
         exit: &Arc<AtomicBool>,
     ) -> Result<(), String> {
-        let endpoint = Endpoint::from_str(engine_url).map_err(|e| e.to_string())?;
-        let channel = endpoint.connect().await.map_err(|e| e.to_string())?;
+        let auth_channel = Self::connect(&config.auth_service_url, &config.tls).await?;
+        let auth_session = AuthSession::new(auth_channel, config.keypair.clone()).await?;
+        stats.auth_session_count.add(1);
+
+        let engine_channel = Self::connect(&config.engine_url, &config.tls).await?;
+        let mut client =
+            BlockEngineRelayerClient::with_interceptor(engine_channel, auth_session.interceptor());
+
+        let (update_sender, update_receiver) = channel(ENGINE_STREAM_BUFFER);
+        let packet_stream = client
+            .start_expiring_packet_stream(ReceiverStream::new(update_receiver))
+            .await
+            .map_err(|e| e.to_string())?
+            .into_inner();
+        let aoi_stream = client
+            .subscribe_accounts_of_interest(AccountsOfInterestRequest {})
+            .await
+            .map_err(|e| e.to_string())?
+            .into_inner();
+        let poi_stream = client
+            .subscribe_programs_of_interest(ProgramsOfInterestRequest {})
+            .await
+            .map_err(|e| e.to_string())?
+            .into_inner();
+        info!("Connected to block engine at {}", config.engine_url);
+
+        let auth_refresh =
+            tokio::spawn(auth_session.keep_fresh(stats.clone(), AUTH_REFRESH_CHECK_INTERVAL));
+        let auth_refresh_abort = auth_refresh.abort_handle();
+        let connection = EngineConnection {
+            auth_refresh,
+            update_sender,
+            packet_stream,
+            aoi_stream,
+            poi_stream,
+        };
+        let result = Self::stream_packets(
+            connection,
+            engine_receiver,
+            interest,
+            stats,
+            metrics_sink,
+            exit,
+        )
+        .await;
+        auth_refresh_abort.abort();
+        result
+    }
+
+    async fn connect(url: &str, tls: &Option<ClientTlsConfig>) -> Result<Channel, String> {
+        let mut endpoint = Endpoint::from_str(url).map_err(|e| e.to_string())?;
+        if let Some(tls) = tls {
+            endpoint = endpoint
+                .tls_config(tls.clone())
+                .map_err(|e| e.to_string())?;
+        }
+        endpoint.connect().await.map_err(|e| e.to_string())
+    }
+
+    async fn stream_packets(
+        mut connection: EngineConnection,
+        engine_receiver: &mut Receiver<PacketBatch>,
+        interest: &InterestSets,
+        stats: &EngineStats,
+        metrics_sink: &dyn MetricsSink,
+        exit: &Arc<AtomicBool>,
+    ) -> Result<(), String> {
+        let mut heartbeat_interval = interval(HEARTBEAT_INTERVAL);
+        let mut metrics_interval = interval(METRICS_INTERVAL);
+        let mut heartbeat_count = 0;
+        let mut last_engine_heartbeat = Instant::now();
+        let mut last_report = Instant::now();
 
-        let mut heartbeat_interval = interval(Duration::from_millis(500));
         while !exit.load(Ordering::Relaxed) {
             select! {
                 _ = heartbeat_interval.tick() => {
-                    info!("Sending heartbeat");
+                    if last_engine_heartbeat.elapsed() > ENGINE_HEARTBEAT_TIMEOUT {
+                        return Err("Block engine heartbeat timed out".to_string());
+                    }
+                    let start = Instant::now();
+                    heartbeat_count += 1;
+                    connection
+                        .update_sender
+                        .send(PacketBatchUpdate {
+                            msg: Some(Msg::Heartbeat(Heartbeat { count: heartbeat_count })),
+                        })
+                        .await
+                        .map_err(|_| "Block engine stream closed".to_string())?;
+                    stats.heartbeat_count.add(1);
+                    stats.heartbeat_elapsed_us.record(start.elapsed().as_micros() as u64);
+                }
+                refresh_result = &mut connection.auth_refresh => {
+                    return Err(refresh_result.unwrap_or_else(|e| format!("Auth refresh task failed: {e}")));
                 }
-                maybe_packet = engine_receiver.recv() => {
-                    if let Some(packet) = maybe_packet {
-                        info!("Received packet, forwarding...");
+                _ = metrics_interval.tick() => {
+                    interest.prune();
+                    stats.engine_packet_sender_len.set(engine_receiver.len() as u64);
+                    stats.accounts_of_interest_len.set(interest.accounts.len() as u64);
+                    stats.programs_of_interest_len.set(interest.programs.len() as u64);
+                    stats.metrics_delay_us.add(
+                        last_report.elapsed().saturating_sub(METRICS_INTERVAL).as_micros() as u64,
+                    );
+                    last_report = Instant::now();
+                    stats.reset_and_report(metrics_sink);
+                }
+                maybe_response = connection.packet_stream.message() => {
+                    match maybe_response {
+                        Ok(Some(_)) => last_engine_heartbeat = Instant::now(),
+                        Ok(None) => return Err("Block engine closed the stream".to_string()),
+                        Err(status) => return Err(format!("Block engine stream error: {status}")),
                     }
                 }
+                maybe_update = connection.aoi_stream.message() => {
+                    let update = maybe_update
+                        .map_err(|status| format!("Accounts of interest stream error: {status}"))?
+                        .ok_or_else(|| {
+                            "Block engine closed accounts of interest stream".to_string()
+                        })?;
+                    let start = Instant::now();
+                    let received = interest.update_accounts(&update.accounts);
+                    stats.aoi_update_count.add(1);
+                    stats.aoi_accounts_received.add(received);
+                    stats.aoi_update_elapsed_us.record(start.elapsed().as_micros() as u64);
+                }
+                maybe_update = connection.poi_stream.message() => {
+                    let update = maybe_update
+                        .map_err(|status| format!("Programs of interest stream error: {status}"))?
+                        .ok_or_else(|| {
+                            "Block engine closed programs of interest stream".to_string()
+                        })?;
+                    let start = Instant::now();
+                    let received = interest.update_programs(&update.programs);
+                    stats.poi_update_count.add(1);
+                    stats.poi_accounts_received.add(received);
+                    stats.poi_update_elapsed_us.record(start.elapsed().as_micros() as u64);
+                }
+                maybe_batch = engine_receiver.recv() => {
+                    let Some(batch) = maybe_batch else {
+                        info!("Engine packet channel closed, closing block engine streams");
+                        return Ok(());
+                    };
+                    stats.num_packets_received.add(batch.len() as u64);
+
+                    let start = Instant::now();
+                    let batch: PacketBatch =
+                        batch.into_iter().filter(|p| interest.is_of_interest(p)).collect();
+                    stats.packet_filter_elapsed_us.record(start.elapsed().as_micros() as u64);
+                    if batch.is_empty() {
+                        continue;
+                    }
+
+                    let start = Instant::now();
+                    let num_packets = batch.len() as u64;
+                    connection
+                        .update_sender
+                        .send(Self::to_packet_batch_update(batch))
+                        .await
+                        .map_err(|_| "Block engine stream closed".to_string())?;
+                    stats.packet_forward_count.add(num_packets);
+                    stats.packet_forward_elapsed_us.record(start.elapsed().as_micros() as u64);
+                }
             }
         }
         Ok(())
     }
+
+    fn to_packet_batch_update(batch: PacketBatch) -> PacketBatchUpdate {
+        PacketBatchUpdate {
+            msg: Some(Msg::Batches(ExpiringPacketBatch {
+                header: Some(Header {
+                    ts: Some(Timestamp::from(SystemTime::now())),
+                }),
+                batch: Some(batch.to_proto()),
+                expiry_ms: PACKET_EXPIRY_MS,
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A signed message with `keys` as its static keys and, if versioned,
+    /// `lookups` address table lookups.
+    fn packet(keys: &[Pubkey], versioned: bool, lookups: u8) -> Packet {
+        let mut data = vec![1];
+        data.extend_from_slice(&[7; 64]);
+        if versioned {
+            data.push(0x80);
+        }
+        data.extend_from_slice(&[1, 0, 0, keys.len() as u8]);
+        for key in keys {
+            data.extend_from_slice(key.as_ref());
+        }
+        data.extend_from_slice(&[0; 32]);
+        data.push(0);
+        if versioned {
+            data.push(lookups);
+            for _ in 0..lookups {
+                data.extend_from_slice(&[0; 32]);
+                data.extend_from_slice(&[1, 0, 0]);
+            }
+        }
+        Packet::new(data, "127.0.0.1:0".parse().unwrap(), false)
+    }
+
+    fn interest(accounts: &[Pubkey], programs: &[Pubkey]) -> InterestSets {
+        let interest = InterestSets::default();
+        let keys = |keys: &[Pubkey]| keys.iter().map(Pubkey::to_string).collect::<Vec<_>>();
+        interest.update_accounts(&keys(accounts));
+        interest.update_programs(&keys(programs));
+        interest
+    }
+
+    #[test]
+    fn forwards_packets_touching_accounts_or_programs_of_interest() {
+        let (account, program, other) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let interest = interest(&[account], &[program]);
+
+        assert!(interest.is_of_interest(&packet(&[other, account], false, 0)));
+        assert!(interest.is_of_interest(&packet(&[other, program], true, 0)));
+        assert!(!interest.is_of_interest(&packet(&[other], false, 0)));
+        assert!(!interest.is_of_interest(&packet(&[other], true, 0)));
+    }
+
+    #[test]
+    fn forwards_packets_using_address_lookup_tables() {
+        let interest = interest(&[Pubkey::new_unique()], &[]);
+        assert!(interest.is_of_interest(&packet(&[Pubkey::new_unique()], true, 1)));
+    }
+
+    #[test]
+    fn forwards_everything_until_both_sets_are_received() {
+        let interest = InterestSets::default();
+        let uninteresting = packet(&[Pubkey::new_unique()], false, 0);
+        assert!(interest.is_of_interest(&uninteresting));
+
+        interest.update_accounts(&[]);
+        assert!(interest.is_of_interest(&uninteresting));
+
+        interest.update_programs(&[]);
+        assert!(!interest.is_of_interest(&uninteresting));
+    }
+
+    #[test]
+    fn ignores_invalid_pubkeys() {
+        let interest = InterestSets::default();
+        let key = Pubkey::new_unique();
+        assert_eq!(
+            interest.update_accounts(&["not a pubkey".to_string(), key.to_string()]),
+            1
+        );
+        assert!(interest.accounts.contains_key(&key));
+    }
 }
@@ -1,100 +1,133 @@
-use log::info;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::metrics::{LatencyHistogram, MetricsSink};
+
+/// Monotonic count accumulated over a reporting interval.
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn add(&self, num: u64) {
+        self.0.fetch_add(num, Ordering::Relaxed);
+    }
+
+    fn take(&self) -> u64 {
+        self.0.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// Point-in-time value, overwritten rather than accumulated. Reporting
+/// leaves it in place until the next `set`.
+#[derive(Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
 
 #[derive(Default)]
 pub struct EngineStats {
-    heartbeat_count: u64,
-    heartbeat_elapsed_us: u64,
+    pub heartbeat_count: Counter,
+    pub heartbeat_elapsed_us: LatencyHistogram,
 
-    aoi_update_count: u64,
-    aoi_update_elapsed_us: u64,
-    aoi_accounts_received: u64,
+    pub aoi_update_count: Counter,
+    pub aoi_update_elapsed_us: LatencyHistogram,
+    pub aoi_accounts_received: Counter,
 
-    poi_update_count: u64,
-    poi_update_elapsed_us: u64,
-    poi_accounts_received: u64,
+    pub poi_update_count: Counter,
+    pub poi_update_elapsed_us: LatencyHistogram,
+    pub poi_accounts_received: Counter,
 
-    num_packets_received: u64,
+    pub num_packets_received: Counter,
 
-    packet_filter_elapsed_us: u64,
-    packet_forward_elapsed_us: u64,
+    pub packet_filter_elapsed_us: LatencyHistogram,
+    pub packet_forward_elapsed_us: LatencyHistogram,
 
-    engine_packet_sender_len: u64,
-    auth_refresh_count: u64,
-    refresh_auth_elapsed_us: u64,
-    packet_forward_count: u64,
-    metrics_delay_us: u64,
+    pub engine_packet_sender_len: Gauge,
+    /// Block engine auth sessions established, one per (re)connect.
+    pub auth_session_count: Counter,
+    pub auth_refresh_count: Counter,
+    pub refresh_auth_elapsed_us: LatencyHistogram,
+    pub packet_forward_count: Counter,
+    pub metrics_delay_us: Counter,
 
-    accounts_of_interest_len: u64,
-    programs_of_interest_len: u64,
-    flush_elapsed_us: u64,
+    pub accounts_of_interest_len: Gauge,
+    pub programs_of_interest_len: Gauge,
+    pub flush_elapsed_us: LatencyHistogram,
 }
 
 impl EngineStats {
-    pub fn increment(&mut self, field: &str, num: u64) {
-        match field {
-            "heartbeat_count" => self.heartbeat_count = self.heartbeat_count.saturating_add(num),
-            "heartbeat_elapsed_us" => {
-                self.heartbeat_elapsed_us = self.heartbeat_elapsed_us.saturating_add(num)
-            }
-            "aoi_update_count" => self.aoi_update_count = self.aoi_update_count.saturating_add(num),
-            "aoi_update_elapsed_us" => {
-                self.aoi_update_elapsed_us = self.aoi_update_elapsed_us.saturating_add(num)
-            }
-            "aoi_accounts_received" => {
-                self.aoi_accounts_received = self.aoi_accounts_received.saturating_add(num)
-            }
-            "poi_update_count" => self.poi_update_count = self.poi_update_count.saturating_add(num),
-            "poi_update_elapsed_us" => {
-                self.poi_update_elapsed_us = self.poi_update_elapsed_us.saturating_add(num)
-            }
-            "poi_accounts_received" => {
-                self.poi_accounts_received = self.poi_accounts_received.saturating_add(num)
-            }
-            "num_packets_received" => {
-                self.num_packets_received = self.num_packets_received.saturating_add(num)
-            }
-            "packet_filter_elapsed_us" => {
-                self.packet_filter_elapsed_us = self.packet_filter_elapsed_us.saturating_add(num)
-            }
-            "packet_forward_elapsed_us" => {
-                self.packet_forward_elapsed_us = self.packet_forward_elapsed_us.saturating_add(num)
-            }
-            "engine_packet_sender_len" => {
-                self.engine_packet_sender_len = std::cmp::max(self.engine_packet_sender_len, num)
-            }
-            "auth_refresh_count" => {
-                self.auth_refresh_count = self.auth_refresh_count.saturating_add(num)
-            }
-            "refresh_auth_elapsed_us" => {
-                self.refresh_auth_elapsed_us = self.refresh_auth_elapsed_us.saturating_add(num)
-            }
-            "packet_forward_count" => {
-                self.packet_forward_count = self.packet_forward_count.saturating_add(num)
-            }
-            "metrics_delay_us" => self.metrics_delay_us = self.metrics_delay_us.saturating_add(num),
-            "accounts_of_interest_len" => {
-                self.accounts_of_interest_len = self.accounts_of_interest_len.saturating_add(num)
-            }
-            "programs_of_interest_len" => {
-                self.programs_of_interest_len = self.programs_of_interest_len.saturating_add(num)
-            }
-            "flush_elapsed_us" => self.flush_elapsed_us = self.flush_elapsed_us.saturating_add(num),
-            _ => {}
+    /// Submits every field for the interval just ended to `sink`, resetting
+    /// the counters and histograms; gauges keep their current value.
+    pub fn reset_and_report(&self, sink: &dyn MetricsSink) {
+        sink.submit(
+            "engine_stats",
+            &[
+                ("heartbeat_count", self.heartbeat_count.take()),
+                ("aoi_update_count", self.aoi_update_count.take()),
+                ("aoi_accounts_received", self.aoi_accounts_received.take()),
+                ("poi_update_count", self.poi_update_count.take()),
+                ("poi_accounts_received", self.poi_accounts_received.take()),
+                ("num_packets_received", self.num_packets_received.take()),
+                ("auth_session_count", self.auth_session_count.take()),
+                ("auth_refresh_count", self.auth_refresh_count.take()),
+                ("packet_forward_count", self.packet_forward_count.take()),
+                ("metrics_delay_us", self.metrics_delay_us.take()),
+            ],
+        );
+        sink.submit_gauges(
+            "engine_stats",
+            &[
+                (
+                    "engine_packet_sender_len",
+                    self.engine_packet_sender_len.get(),
+                ),
+                (
+                    "accounts_of_interest_len",
+                    self.accounts_of_interest_len.get(),
+                ),
+                (
+                    "programs_of_interest_len",
+                    self.programs_of_interest_len.get(),
+                ),
+            ],
+        );
+        for (field, histogram) in [
+            ("heartbeat_elapsed_us", &self.heartbeat_elapsed_us),
+            ("aoi_update_elapsed_us", &self.aoi_update_elapsed_us),
+            ("poi_update_elapsed_us", &self.poi_update_elapsed_us),
+            ("packet_filter_elapsed_us", &self.packet_filter_elapsed_us),
+            ("packet_forward_elapsed_us", &self.packet_forward_elapsed_us),
+            ("refresh_auth_elapsed_us", &self.refresh_auth_elapsed_us),
+            ("flush_elapsed_us", &self.flush_elapsed_us),
+        ] {
+            sink.submit_histogram("engine_stats", field, &histogram.take());
         }
     }
+}
 
-    pub fn report(&self) {
-        info!(
-            "Engine Stats - Heartbeat: {}, AOI Updates: {}, POI Updates: {}, Packets Received: {}, Packet Filter Time: {}, Packet Forward Time: {}, Auth Refresh: {}, Metrics Delay: {}, Flush Time: {}",
-            self.heartbeat_count,
-            self.aoi_update_count,
-            self.poi_update_count,
-            self.num_packets_received,
-            self.packet_filter_elapsed_us,
-            self.packet_forward_elapsed_us,
-            self.auth_refresh_count,
-            self.metrics_delay_us,
-            self.flush_elapsed_us,
-        );
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_reset_but_gauges_keep_their_value() {
+        let counter = Counter::default();
+        counter.add(3);
+        assert_eq!(counter.take(), 3);
+        assert_eq!(counter.take(), 0);
+
+        let gauge = Gauge::default();
+        gauge.set(7);
+        assert_eq!(gauge.get(), 7);
+        assert_eq!(gauge.get(), 7);
+        gauge.set(2);
+        assert_eq!(gauge.get(), 2);
     }
 }
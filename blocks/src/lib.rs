@@ -1,2 +1,6 @@
+pub mod block_auth;
 pub mod block_relayer;
 pub mod block_stats;
+pub mod metrics;
+pub mod packet;
+pub mod supervisor;
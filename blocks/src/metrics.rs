@@ -0,0 +1,155 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use log::info;
+
+/// Destination for interval metrics.
+pub trait MetricsSink: Send + Sync {
+    /// Counts accumulated over the interval just ended.
+    fn submit(&self, name: &'static str, points: &[(&'static str, u64)]);
+
+    /// Point-in-time values at the end of the interval.
+    fn submit_gauges(&self, name: &'static str, points: &[(&'static str, u64)]) {
+        self.submit(name, points);
+    }
+
+    /// Distribution of `field` over the interval just ended.
+    fn submit_histogram(
+        &self,
+        _name: &'static str,
+        _field: &'static str,
+        _histogram: &HistogramSnapshot,
+    ) {
+    }
+}
+
+/// Writes each submission as a single info log line.
+#[derive(Default)]
+pub struct LogMetricsSink;
+
+impl MetricsSink for LogMetricsSink {
+    fn submit(&self, name: &'static str, points: &[(&'static str, u64)]) {
+        let fields: Vec<String> = points.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        info!("{name}: {}", fields.join(", "));
+    }
+
+    fn submit_histogram(
+        &self,
+        name: &'static str,
+        field: &'static str,
+        histogram: &HistogramSnapshot,
+    ) {
+        if histogram.count == 0 {
+            return;
+        }
+        info!(
+            "{name}: {field} count={}, p50={}, p90={}, p99={}, max={}",
+            histogram.count,
+            histogram.percentile(0.50),
+            histogram.percentile(0.90),
+            histogram.percentile(0.99),
+            histogram.max_us,
+        );
+    }
+}
+
+/// Forwards every submission to each of the wrapped sinks.
+pub struct MetricsSinks(pub Vec<Arc<dyn MetricsSink>>);
+
+impl MetricsSink for MetricsSinks {
+    fn submit(&self, name: &'static str, points: &[(&'static str, u64)]) {
+        for sink in &self.0 {
+            sink.submit(name, points);
+        }
+    }
+
+    fn submit_gauges(&self, name: &'static str, points: &[(&'static str, u64)]) {
+        for sink in &self.0 {
+            sink.submit_gauges(name, points);
+        }
+    }
+
+    fn submit_histogram(
+        &self,
+        name: &'static str,
+        field: &'static str,
+        histogram: &HistogramSnapshot,
+    ) {
+        for sink in &self.0 {
+            sink.submit_histogram(name, field, histogram);
+        }
+    }
+}
+
+/// Upper bounds, in microseconds, of the latency histogram buckets. Values
+/// above the last bound land in an overflow bucket.
+pub const LATENCY_BUCKETS_US: [u64; 12] = [
+    100, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000,
+];
+
+/// Lock-free fixed-bucket histogram of microsecond latencies.
+#[derive(Default)]
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS_US.len() + 1],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+    max_us: AtomicU64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HistogramSnapshot {
+    pub buckets: [u64; LATENCY_BUCKETS_US.len() + 1],
+    pub count: u64,
+    pub sum_us: u64,
+    pub max_us: u64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, value_us: u64) {
+        let bucket = LATENCY_BUCKETS_US
+            .iter()
+            .position(|bound| value_us <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_US.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(value_us, Ordering::Relaxed);
+        self.max_us.fetch_max(value_us, Ordering::Relaxed);
+    }
+
+    /// Returns the recorded values and resets the histogram.
+    pub fn take(&self) -> HistogramSnapshot {
+        let mut snapshot = HistogramSnapshot::default();
+        for (bucket, value) in self.buckets.iter().zip(snapshot.buckets.iter_mut()) {
+            *value = bucket.swap(0, Ordering::Relaxed);
+        }
+        snapshot.count = self.count.swap(0, Ordering::Relaxed);
+        snapshot.sum_us = self.sum_us.swap(0, Ordering::Relaxed);
+        snapshot.max_us = self.max_us.swap(0, Ordering::Relaxed);
+        snapshot
+    }
+}
+
+impl HistogramSnapshot {
+    /// Returns the upper bound of the bucket holding the `p` quantile, or
+    /// the observed maximum for the overflow bucket.
+    pub fn percentile(&self, p: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let target = ((self.count as f64) * p).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return LATENCY_BUCKETS_US
+                    .get(i)
+                    .copied()
+                    .unwrap_or(self.max_us)
+                    .min(self.max_us);
+            }
+        }
+        self.max_us
+    }
+}
@@ -0,0 +1,323 @@
+//! Packets passed between relayer stages, and zero-copy accessors for the
+//! serialized transactions they carry.
+//!
+//! A wire transaction is a compact-u16 signature count, the 64-byte
+//! signatures, then the message. Legacy messages start with the 3-byte
+//! header; versioned messages prefix it with a byte that has the high bit
+//! set. The header is followed by a compact-u16 account count and the
+//! 32-byte account keys, signers first. After the keys come the recent
+//! blockhash and the instructions; versioned messages end with their
+//! address table lookups.
+
+use std::{net::SocketAddr, sync::Arc, time::Instant};
+
+use builder_block::proto::packet::{
+    Meta as ProtoMeta, Packet as ProtoPacket, PacketBatch as ProtoPacketBatch, PacketFlags,
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+const SIGNATURE_LEN: usize = 64;
+const PUBKEY_LEN: usize = 32;
+const MESSAGE_HEADER_LEN: usize = 3;
+const MESSAGE_VERSION_PREFIX: u8 = 0x80;
+const BLOCKHASH_LEN: usize = 32;
+
+/// Decodes a compact-u16 at `offset`, returning the value and the offset
+/// just past it.
+pub fn read_compact_u16(bytes: &[u8], offset: usize) -> Option<(u16, usize)> {
+    let mut value: u32 = 0;
+    for i in 0..3 {
+        let byte = *bytes.get(offset + i)?;
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return u16::try_from(value).ok().map(|v| (v, offset + i + 1));
+        }
+    }
+    None
+}
+
+/// Returns the first signature, which identifies the transaction.
+pub fn first_signature(packet: &[u8]) -> Option<Signature> {
+    let (num_signatures, offset) = read_compact_u16(packet, 0)?;
+    if num_signatures == 0 {
+        return None;
+    }
+    let bytes = packet.get(offset..offset + SIGNATURE_LEN)?;
+    Some(Signature::new(bytes))
+}
+
+/// Returns the first account key, which is the fee payer and first signer.
+pub fn first_signer(packet: &[u8]) -> Option<Pubkey> {
+    account_keys(packet)?.next()
+}
+
+/// Returns whether the message is versioned and the offset of its header.
+fn message_header(packet: &[u8]) -> Option<(bool, usize)> {
+    let (num_signatures, offset) = read_compact_u16(packet, 0)?;
+    let offset = offset + num_signatures as usize * SIGNATURE_LEN;
+    if *packet.get(offset)? & MESSAGE_VERSION_PREFIX != 0 {
+        Some((true, offset + 1))
+    } else {
+        Some((false, offset))
+    }
+}
+
+/// Iterates over the static account keys of the message.
+pub fn account_keys(packet: &[u8]) -> Option<impl Iterator<Item = Pubkey> + '_> {
+    let (_, offset) = message_header(packet)?;
+    let (num_keys, offset) = read_compact_u16(packet, offset + MESSAGE_HEADER_LEN)?;
+    let keys = packet.get(offset..offset + num_keys as usize * PUBKEY_LEN)?;
+    Some(
+        keys.chunks_exact(PUBKEY_LEN)
+            .map(|key| Pubkey::try_from(key).unwrap()),
+    )
+}
+
+/// Whether the message loads accounts from address lookup tables, which
+/// means its static keys are not all the accounts it touches. Legacy
+/// messages never do.
+pub fn uses_address_lookups(packet: &[u8]) -> Option<bool> {
+    let (versioned, offset) = message_header(packet)?;
+    if !versioned {
+        return Some(false);
+    }
+    let (num_keys, offset) = read_compact_u16(packet, offset + MESSAGE_HEADER_LEN)?;
+    let offset = offset + num_keys as usize * PUBKEY_LEN + BLOCKHASH_LEN;
+    let (num_instructions, mut offset) = read_compact_u16(packet, offset)?;
+    for _ in 0..num_instructions {
+        // Program id index, then the account indexes and the data.
+        let (num_accounts, next) = read_compact_u16(packet, offset + 1)?;
+        let (data_len, next) = read_compact_u16(packet, next + num_accounts as usize)?;
+        offset = next + data_len as usize;
+    }
+    let (num_lookups, _) = read_compact_u16(packet, offset)?;
+    Some(num_lookups > 0)
+}
+
+/// Returns the program invoked by the first instruction.
+pub fn first_program_id(packet: &[u8]) -> Option<Pubkey> {
+    let (_, offset) = message_header(packet)?;
+    let (num_keys, keys_offset) = read_compact_u16(packet, offset + MESSAGE_HEADER_LEN)?;
+    let offset = keys_offset + num_keys as usize * PUBKEY_LEN + BLOCKHASH_LEN;
+    let (num_instructions, offset) = read_compact_u16(packet, offset)?;
+    if num_instructions == 0 {
+        return None;
+    }
+    // Program ids are always static keys, never loaded from lookup tables.
+    account_keys(packet)?.nth(*packet.get(offset)? as usize)
+}
+
+/// Whether the transaction is a vote, judged by the program its first
+/// instruction invokes. Cheap enough to run on every packet, unlike the
+/// filter stage's full check.
+pub fn is_simple_vote(packet: &[u8]) -> bool {
+    first_program_id(packet).map_or(false, |id| solana_sdk::vote::program::check_id(&id))
+}
+
+#[derive(Clone, Debug)]
+pub struct PacketMeta {
+    /// Address the packet was received from.
+    pub addr: SocketAddr,
+    /// When the TPU received the packet.
+    pub arrival: Instant,
+    /// Whether the sender is a staked node.
+    pub staked: bool,
+    pub signature: Option<Signature>,
+    /// Whether the transaction is a vote: set on creation by
+    /// [`is_simple_vote`], and by the forwarder's filter stage when any
+    /// instruction invokes the vote program.
+    pub is_vote: bool,
+}
+
+/// A serialized transaction and its metadata. The payload is shared, so
+/// cloning a packet never copies the transaction bytes.
+#[derive(Clone, Debug)]
+pub struct Packet {
+    pub meta: PacketMeta,
+    data: Arc<[u8]>,
+}
+
+impl Packet {
+    pub fn new(data: impl Into<Arc<[u8]>>, addr: SocketAddr, staked: bool) -> Self {
+        let data = data.into();
+        Packet {
+            meta: PacketMeta {
+                addr,
+                arrival: Instant::now(),
+                staked,
+                signature: first_signature(&data),
+                is_vote: is_simple_vote(&data),
+            },
+            data,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Packets moved between stages together, so channels carry one message
+/// per batch rather than per packet.
+#[derive(Clone, Debug, Default)]
+pub struct PacketBatch {
+    packets: Vec<Packet>,
+}
+
+impl PacketBatch {
+    pub fn new(packets: Vec<Packet>) -> Self {
+        PacketBatch { packets }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        PacketBatch {
+            packets: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, packet: Packet) {
+        self.packets.push(packet);
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Packet> {
+        self.packets.iter()
+    }
+
+    /// Copies the batch into its gRPC representation.
+    pub fn to_proto(&self) -> ProtoPacketBatch {
+        let packets = self
+            .packets
+            .iter()
+            .map(|packet| ProtoPacket {
+                data: packet.data().to_vec(),
+                meta: Some(ProtoMeta {
+                    size: packet.data().len() as u64,
+                    addr: packet.meta.addr.ip().to_string(),
+                    port: packet.meta.addr.port() as u32,
+                    flags: Some(PacketFlags {
+                        from_staked_node: packet.meta.staked,
+                        simple_vote_tx: packet.meta.is_vote,
+                        ..PacketFlags::default()
+                    }),
+                    // Only whether the sender is staked is known, not how much.
+                    sender_stake: 0,
+                }),
+            })
+            .collect();
+        ProtoPacketBatch { packets }
+    }
+}
+
+impl IntoIterator for PacketBatch {
+    type Item = Packet;
+    type IntoIter = std::vec::IntoIter<Packet>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.packets.into_iter()
+    }
+}
+
+impl FromIterator<Packet> for PacketBatch {
+    fn from_iter<I: IntoIterator<Item = Packet>>(iter: I) -> Self {
+        PacketBatch {
+            packets: iter.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_proto_keeps_sender_address_and_staked_flag() {
+        let addr: SocketAddr = "10.0.0.1:8001".parse().unwrap();
+        let batch = PacketBatch::new(vec![
+            Packet::new(vec![0], addr, true),
+            Packet::new(vec![0], addr, false),
+        ]);
+
+        let proto = batch.to_proto();
+        let meta = proto.packets[0].meta.as_ref().unwrap();
+        assert_eq!(meta.addr, "10.0.0.1");
+        assert_eq!(meta.port, 8001);
+        assert!(meta.flags.as_ref().unwrap().from_staked_node);
+        let meta = proto.packets[1].meta.as_ref().unwrap();
+        assert!(!meta.flags.as_ref().unwrap().from_staked_node);
+    }
+
+    #[test]
+    fn detects_address_table_lookups() {
+        let mut message = vec![1];
+        message.extend_from_slice(&[7; SIGNATURE_LEN]);
+        message.extend_from_slice(&[MESSAGE_VERSION_PREFIX, 1, 0, 1, 1]);
+        message.extend_from_slice(&[0; PUBKEY_LEN + BLOCKHASH_LEN]);
+        // One instruction with a single account and two bytes of data.
+        message.extend_from_slice(&[1, 0, 1, 0, 2, 9, 9]);
+
+        let mut without_lookups = message.clone();
+        without_lookups.push(0);
+        assert_eq!(uses_address_lookups(&without_lookups), Some(false));
+
+        let mut with_lookups = message.clone();
+        with_lookups.push(1);
+        with_lookups.extend_from_slice(&[0; PUBKEY_LEN]);
+        with_lookups.extend_from_slice(&[1, 0, 0]);
+        assert_eq!(uses_address_lookups(&with_lookups), Some(true));
+
+        assert_eq!(uses_address_lookups(&message), None);
+        let mut legacy = message[..1 + SIGNATURE_LEN].to_vec();
+        legacy.extend_from_slice(&message[2 + SIGNATURE_LEN..]);
+        assert_eq!(uses_address_lookups(&legacy), Some(false));
+    }
+
+    #[test]
+    fn detects_votes_by_first_instruction_program() {
+        let payer = Pubkey::new_unique();
+        let vote_program = solana_sdk::vote::program::id();
+        let message = |program_index: u8| {
+            let mut message = vec![1];
+            message.extend_from_slice(&[7; SIGNATURE_LEN]);
+            message.extend_from_slice(&[1, 0, 1, 2]);
+            message.extend_from_slice(payer.as_ref());
+            message.extend_from_slice(vote_program.as_ref());
+            message.extend_from_slice(&[0; BLOCKHASH_LEN]);
+            message.extend_from_slice(&[1, program_index, 1, 0, 0]);
+            message
+        };
+        let addr = "127.0.0.1:0".parse().unwrap();
+
+        assert_eq!(first_program_id(&message(1)), Some(vote_program));
+        assert!(Packet::new(message(1), addr, false).meta.is_vote);
+        assert!(!Packet::new(message(0), addr, false).meta.is_vote);
+        assert_eq!(first_program_id(&message(2)), None);
+        assert!(!Packet::new(vec![0], addr, false).meta.is_vote);
+    }
+
+    #[test]
+    fn account_keys_skip_the_version_prefix() {
+        let key = Pubkey::new_unique();
+        let mut legacy = vec![1];
+        legacy.extend_from_slice(&[7; SIGNATURE_LEN]);
+        legacy.extend_from_slice(&[1, 0, 0, 1]);
+        legacy.extend_from_slice(key.as_ref());
+        assert_eq!(first_signer(&legacy), Some(key));
+
+        let mut versioned = legacy[..1 + SIGNATURE_LEN].to_vec();
+        versioned.push(MESSAGE_VERSION_PREFIX);
+        versioned.extend_from_slice(&legacy[1 + SIGNATURE_LEN..]);
+        assert_eq!(first_signer(&versioned), Some(key));
+        assert_eq!(
+            first_signature(&versioned),
+            Some(Signature::new(&[7; SIGNATURE_LEN]))
+        );
+    }
+}
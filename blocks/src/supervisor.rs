@@ -0,0 +1,35 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, Builder, JoinHandle},
+    time::Duration,
+};
+
+use log::error;
+
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Spawns a named thread running `work`. If `work` panics it is restarted
+/// after a short backoff, unless `exit` is set; the thread ends once `work`
+/// returns normally.
+pub fn spawn_supervised<F>(name: String, exit: Arc<AtomicBool>, mut work: F) -> JoinHandle<()>
+where
+    F: FnMut() + Send + 'static,
+{
+    Builder::new()
+        .name(name.clone())
+        .spawn(move || loop {
+            if panic::catch_unwind(AssertUnwindSafe(&mut work)).is_ok() {
+                return;
+            }
+            if exit.load(Ordering::Relaxed) {
+                return;
+            }
+            error!("{name} panicked, restarting in {RESTART_BACKOFF:?}");
+            thread::sleep(RESTART_BACKOFF);
+        })
+        .unwrap()
+}
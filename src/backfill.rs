@@ -0,0 +1,131 @@
+use crate::block::fetch_block;
+use crate::rpc_pool::{is_skipped_slot, RpcPool};
+use crate::store::BlockStore;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use solana_sdk::clock::Slot;
+use std::thread::sleep;
+use std::time::Duration;
+
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+pub struct BackfillConfig {
+    pub start: Slot,
+    pub end: Slot,
+    /// Number of processed slots between store checkpoints.
+    pub checkpoint_interval: u64,
+    pub max_retries: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct BackfillSummary {
+    pub fetched: u64,
+    /// Slots the node reported as skipped or missing, which have no block.
+    pub skipped: u64,
+    pub failed: u64,
+}
+
+/// Fetches every slot in `[start, end]` into the store, resuming after the
+/// store's checkpoint if an earlier run of the same range left one. Skipped
+/// slots are counted as processed. Slots that still fail after `max_retries`
+/// are recorded in the store instead of aborting the run.
+pub fn backfill(
+    pool: &RpcPool,
+    config: &BackfillConfig,
+    store: &mut BlockStore,
+) -> Result<BackfillSummary> {
+    if config.start > config.end {
+        return Err(anyhow!(
+            "Invalid backfill range: start {} is after end {}",
+            config.start,
+            config.end
+        ));
+    }
+    let range = (config.start, config.end);
+    let first = match store.checkpoint_for(range) {
+        Some(checkpoint) => checkpoint.saturating_add(1),
+        None => config.start,
+    };
+    info!(
+        "Backfilling slots {}..={} (resuming at {})",
+        config.start, config.end, first
+    );
+
+    let mut summary = BackfillSummary::default();
+    let mut since_checkpoint = 0;
+    for slot in first..=config.end {
+        fetch_into_store(pool, slot, config.max_retries, store, &mut summary);
+        store.set_checkpoint(range, slot);
+        since_checkpoint += 1;
+        if since_checkpoint >= config.checkpoint_interval {
+            store.save()?;
+            since_checkpoint = 0;
+        }
+    }
+    store.save()?;
+
+    info!(
+        "Backfill complete: {} fetched, {} skipped, {} failed",
+        summary.fetched, summary.skipped, summary.failed
+    );
+    Ok(summary)
+}
+
+/// Retries every slot in the store's failed list, removing the ones that
+/// now succeed or turn out to be skipped.
+pub fn retry_failed(
+    pool: &RpcPool,
+    max_retries: u32,
+    store: &mut BlockStore,
+) -> Result<BackfillSummary> {
+    let slots: Vec<Slot> = store.failed_slots.iter().copied().collect();
+    info!("Retrying {} failed slots", slots.len());
+
+    let mut summary = BackfillSummary::default();
+    for slot in slots {
+        fetch_into_store(pool, slot, max_retries, store, &mut summary);
+        store.save()?;
+    }
+
+    info!(
+        "Retry complete: {} fetched, {} skipped, {} still failing",
+        summary.fetched, summary.skipped, summary.failed
+    );
+    Ok(summary)
+}
+
+fn fetch_into_store(
+    pool: &RpcPool,
+    slot: Slot,
+    max_retries: u32,
+    store: &mut BlockStore,
+    summary: &mut BackfillSummary,
+) {
+    let mut attempt = 0;
+    loop {
+        match fetch_block(pool, slot) {
+            Ok(block) => {
+                store.insert_block(block);
+                summary.fetched += 1;
+                return;
+            }
+            Err(e) if is_skipped_slot(&e) => {
+                info!("Slot {} was skipped, no block to fetch", slot);
+                store.record_skipped(slot);
+                summary.skipped += 1;
+                return;
+            }
+            Err(e) if attempt < max_retries => {
+                attempt += 1;
+                warn!("Attempt {} for slot {} failed: {:?}", attempt, slot, e);
+                sleep(RETRY_BACKOFF * attempt);
+            }
+            Err(e) => {
+                warn!("Giving up on slot {}: {:?}", slot, e);
+                store.record_failure(slot);
+                summary.failed += 1;
+                return;
+            }
+        }
+    }
+}
@@ -3,6 +3,21 @@ use anyhow::Result;
 use log::info;
 use sha2::{Digest, Sha256};
 
+/// Seed hashed in place of a previous hash for the first block of a chain.
+pub const GENESIS_SEED: &[u8] = b"default";
+
+/// Derives the block for `slot` from the previous block's hash.
+///
+/// # Compatibility
+///
+/// The derived hash is part of our chain format and is stable across
+/// releases: it is the lowercase hex SHA-256 of the slot as 8 little-endian
+/// bytes followed by the UTF-8 bytes of `previous_hash`, or [`GENESIS_SEED`]
+/// when there is no previous block. Note that `Some("")` and `None` give
+/// different hashes, but `Some("default")` hashes exactly like `None`, since
+/// the seed is not tagged. Real previous hashes are 64 hex characters and
+/// cannot collide with the seed. Any change to this derivation forks existing
+/// chains and must fail the golden vectors in `tests/vectors/build_block.json`.
 pub fn build_block(slot: u64, previous_hash: Option<&str>) -> Result<SolanaBlock> {
     info!("Building block for slot: {}", slot);
     let mut hasher = Sha256::new();
@@ -12,7 +27,7 @@ pub fn build_block(slot: u64, previous_hash: Option<&str>) -> Result<SolanaBlock
     if let Some(prev) = previous_hash {
         hasher.update(prev.as_bytes());
     } else {
-        hasher.update(b"default");
+        hasher.update(GENESIS_SEED);
     }
 
     let hash_result = hasher.finalize();
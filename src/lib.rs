@@ -1,4 +1,8 @@
+pub mod backfill;
 pub mod block;
 pub mod builder;
 pub mod organizer;
+pub mod rpc_pool;
 pub mod sender;
+pub mod slot_time;
+pub mod store;
@@ -1,5 +1,12 @@
 use clap::{Parser, Subcommand};
 use log::{error, info};
+use solana_block_builder::backfill::{backfill, retry_failed, BackfillConfig, BackfillSummary};
+use solana_block_builder::organizer::{organize_blocks, print_blocks};
+use solana_block_builder::rpc_pool::RpcPool;
+use solana_block_builder::slot_time::resolve_window;
+use solana_block_builder::store::BlockStore;
+use std::path::PathBuf;
+use std::process::ExitCode;
 
 const DEFAULT_RPC_URL: &str = "https://api.devnet.solana.com";
 
@@ -7,6 +14,10 @@ const DEFAULT_RPC_URL: &str = "https://api.devnet.solana.com";
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// Comma-separated Solana RPC URLs; calls fail over between them.
+    #[arg(long, global = true, value_delimiter = ',', default_value = DEFAULT_RPC_URL)]
+    rpc_url: Vec<String>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -19,9 +30,49 @@ enum Commands {
         /// Specify a starting slot (optional).
         #[arg(short, long)]
         start: Option<u64>,
+        /// Last slot to fetch (defaults to the starting slot).
+        #[arg(short, long)]
+        end: Option<u64>,
+        /// Start at the first slot produced at or after this RFC3339 time.
+        #[arg(long, conflicts_with = "start")]
+        since: Option<String>,
+        /// End at the last slot produced at or before this RFC3339 time.
+        #[arg(long, conflicts_with = "end")]
+        until: Option<String>,
+        /// Path to the block store file.
+        #[arg(long, default_value = "blocks.json")]
+        store: PathBuf,
+        /// Fetch the whole start..=end range, resuming from the store's checkpoint.
+        #[arg(long)]
+        backfill: bool,
+        /// Retry the slots recorded as failed by a previous backfill.
+        #[arg(long, conflicts_with = "backfill")]
+        retry_failed: bool,
+        /// Number of slots processed between checkpoints.
+        #[arg(long, default_value_t = 100)]
+        checkpoint_interval: u64,
+        /// Number of retries before a slot is recorded as failed.
+        #[arg(long, default_value_t = 3)]
+        max_retries: u32,
     },
     /// Organize fetched blocks.
-    Organize {},
+    Organize {
+        /// Path to the block store file.
+        #[arg(long, default_value = "blocks.json")]
+        store: PathBuf,
+        /// Only include blocks from this slot onwards.
+        #[arg(short, long)]
+        start: Option<u64>,
+        /// Only include blocks up to this slot.
+        #[arg(short, long)]
+        end: Option<u64>,
+        /// Only include blocks produced at or after this RFC3339 time.
+        #[arg(long, conflicts_with = "start")]
+        since: Option<String>,
+        /// Only include blocks produced at or before this RFC3339 time.
+        #[arg(long, conflicts_with = "end")]
+        until: Option<String>,
+    },
     /// Build a new block.
     Build {
         /// The slot number for the new block.
@@ -45,19 +96,122 @@ enum Commands {
     },
 }
 
-fn main() {
+fn main() -> ExitCode {
     env_logger::init();
     let cli = Cli::parse();
     info!("Starting solana-block-builder");
 
     match &cli.command {
-        Some(Commands::Fetch { start }) => {
+        Some(Commands::Fetch {
+            start,
+            end,
+            since,
+            until,
+            store,
+            backfill: backfill_mode,
+            retry_failed: retry_mode,
+            checkpoint_interval,
+            max_retries,
+        }) => {
             info!("Executing fetch command with starting slot: {:?}", start);
             println!("Fetching blocks from the Solana blockchain");
+            let pool = match RpcPool::new(&cli.rpc_url) {
+                Ok(p) => p,
+                Err(e) => {
+                    error!("Failed to create RPC pool: {:?}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let (start, end) =
+                match resolve_window(&pool, *start, *end, since.as_deref(), until.as_deref()) {
+                    Ok(window) => window,
+                    Err(e) => {
+                        error!("Failed to resolve time window: {:?}", e);
+                        return ExitCode::FAILURE;
+                    }
+                };
+            let mut block_store = match BlockStore::open(store) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Failed to open block store: {:?}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let result = if *retry_mode {
+                retry_failed(&pool, *max_retries, &mut block_store)
+            } else if *backfill_mode {
+                let (Some(start), Some(end)) = (start, end) else {
+                    error!("Backfill requires both --start and --end");
+                    return ExitCode::FAILURE;
+                };
+                let config = BackfillConfig {
+                    start,
+                    end,
+                    checkpoint_interval: *checkpoint_interval,
+                    max_retries: *max_retries,
+                };
+                backfill(&pool, &config, &mut block_store)
+            } else {
+                let Some(start) = start else {
+                    error!("Fetch requires --start, or --backfill/--retry-failed");
+                    return ExitCode::FAILURE;
+                };
+                fetch_range(&pool, &mut block_store, start, end.unwrap_or(start))
+            };
+            match result {
+                Ok(summary) => println!(
+                    "Fetched {} blocks, {} skipped slots, {} failed slots.",
+                    summary.fetched, summary.skipped, summary.failed
+                ),
+                Err(e) => {
+                    error!("Failed to fetch blocks: {:?}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
         }
-        Some(Commands::Organize {}) => {
+        Some(Commands::Organize {
+            store,
+            start,
+            end,
+            since,
+            until,
+        }) => {
             info!("Executing organize command");
             println!("Organizing blocks");
+            let (start, end) = if since.is_some() || until.is_some() {
+                let window = RpcPool::new(&cli.rpc_url).and_then(|pool| {
+                    resolve_window(&pool, *start, *end, since.as_deref(), until.as_deref())
+                });
+                match window {
+                    Ok(window) => window,
+                    Err(e) => {
+                        error!("Failed to resolve time window: {:?}", e);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            } else {
+                (*start, *end)
+            };
+            let blocks = match BlockStore::open(store) {
+                Ok(s) => s.into_blocks(),
+                Err(e) => {
+                    error!("Failed to open block store: {:?}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let blocks = blocks
+                .into_iter()
+                .filter(|b| {
+                    start.map_or(true, |s| b.slot >= s) && end.map_or(true, |e| b.slot <= e)
+                })
+                .collect();
+            match organize_blocks(blocks) {
+                Ok(blocks) => print_blocks(&blocks),
+                Err(e) => {
+                    error!("Failed to organize blocks: {:?}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
         }
         Some(Commands::Build { slot, previous }) => {
             info!(
@@ -69,7 +223,7 @@ fn main() {
                 Ok(b) => b,
                 Err(e) => {
                     error!("Failed to build block: {:?}", e);
-                    return;
+                    return ExitCode::FAILURE;
                 }
             };
             println!(
@@ -91,16 +245,42 @@ fn main() {
                 Ok(b) => b,
                 Err(e) => {
                     error!("Failed to build block: {:?}", e);
-                    return;
+                    return ExitCode::FAILURE;
                 }
             };
-            match solana_block_builder::sender::send_block(DEFAULT_RPC_URL, &block, keypair) {
+            let pool = match RpcPool::new(&cli.rpc_url) {
+                Ok(p) => p,
+                Err(e) => {
+                    error!("Failed to create RPC pool: {:?}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            match solana_block_builder::sender::send_block(&pool, &block, keypair) {
                 Ok(_) => println!("Block sent successfully."),
-                Err(e) => error!("Failed to send block: {:?}", e),
+                Err(e) => {
+                    error!("Failed to send block: {:?}", e);
+                    return ExitCode::FAILURE;
+                }
             }
         }
         None => {
             println!("No command provided. Use --help for more information.");
         }
     }
+    ExitCode::SUCCESS
+}
+
+fn fetch_range(
+    pool: &RpcPool,
+    store: &mut BlockStore,
+    start: u64,
+    end: u64,
+) -> anyhow::Result<BackfillSummary> {
+    let mut summary = BackfillSummary::default();
+    for slot in start..=end {
+        store.insert_block(solana_block_builder::block::fetch_block(pool, slot)?);
+        summary.fetched += 1;
+    }
+    store.save()?;
+    Ok(summary)
 }
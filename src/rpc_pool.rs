@@ -0,0 +1,159 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_custom_error::{
+    JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_SLOT_SKIPPED, JSON_RPC_SERVER_ERROR_SLOT_SKIPPED,
+};
+use solana_client::rpc_request::RpcError;
+use solana_sdk::clock::Slot;
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Health checks run inline on the calling thread, so a hung node must not
+/// stall them for the client's default 30s timeout.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+struct RpcNode {
+    url: String,
+    client: RpcClient,
+    health_client: RpcClient,
+}
+
+#[derive(Clone, Copy, Default)]
+struct NodeHealth {
+    healthy: bool,
+    slot: Slot,
+}
+
+struct PoolState {
+    health: Vec<NodeHealth>,
+    last_check: Option<Instant>,
+}
+
+/// A set of RPC endpoints that routes each call to the most up-to-date
+/// healthy node and fails over to the next one when a node fails.
+pub struct RpcPool {
+    nodes: Vec<RpcNode>,
+    state: Mutex<PoolState>,
+}
+
+impl RpcPool {
+    pub fn new(rpc_urls: &[String]) -> Result<Self> {
+        if rpc_urls.is_empty() {
+            return Err(anyhow!("RPC pool requires at least one URL"));
+        }
+        let nodes: Vec<RpcNode> = rpc_urls
+            .iter()
+            .map(|url| {
+                info!("Connecting to Solana RPC at {}", url);
+                RpcNode {
+                    url: url.clone(),
+                    client: RpcClient::new_with_commitment(
+                        url.clone(),
+                        CommitmentConfig::confirmed(),
+                    ),
+                    health_client: RpcClient::new_with_timeout_and_commitment(
+                        url.clone(),
+                        HEALTH_CHECK_TIMEOUT,
+                        CommitmentConfig::confirmed(),
+                    ),
+                }
+            })
+            .collect();
+        let state = PoolState {
+            health: vec![NodeHealth::default(); nodes.len()],
+            last_check: None,
+        };
+        Ok(RpcPool {
+            nodes,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Queries `getSlot` on every node and records which ones responded.
+    pub fn refresh_health(&self) {
+        let health: Vec<NodeHealth> = self
+            .nodes
+            .iter()
+            .map(|node| match node.health_client.get_slot() {
+                Ok(slot) => NodeHealth {
+                    healthy: true,
+                    slot,
+                },
+                Err(e) => {
+                    warn!("RPC node {} failed health check: {:?}", node.url, e);
+                    NodeHealth::default()
+                }
+            })
+            .collect();
+        let mut state = self.state.lock().unwrap();
+        state.health = health;
+        state.last_check = Some(Instant::now());
+    }
+
+    /// Runs `op` against the nodes in routing order until one succeeds.
+    /// Healthy nodes are tried first, highest slot first; unhealthy nodes
+    /// are still tried as a last resort.
+    ///
+    /// Only transport errors and HTTP 5xx responses fail over. JSON-RPC
+    /// errors, such as a skipped slot, are returned straight away with the
+    /// [`ClientError`] available through `downcast_ref`.
+    pub fn call<T>(&self, op: impl Fn(&RpcClient) -> Result<T, ClientError>) -> Result<T> {
+        let mut last_error = None;
+        for index in self.routing_order() {
+            let node = &self.nodes[index];
+            match op(&node.client) {
+                Ok(value) => return Ok(value),
+                Err(e) if !is_node_failure(&e) => return Err(e.into()),
+                Err(e) => {
+                    warn!("RPC call to {} failed, failing over: {:?}", node.url, e);
+                    self.state.lock().unwrap().health[index].healthy = false;
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(anyhow!("All RPC nodes failed: {:?}", last_error))
+    }
+
+    fn routing_order(&self) -> Vec<usize> {
+        let stale = self
+            .state
+            .lock()
+            .unwrap()
+            .last_check
+            .map_or(true, |t| t.elapsed() >= HEALTH_CHECK_INTERVAL);
+        if stale {
+            self.refresh_health();
+        }
+
+        let health = self.state.lock().unwrap().health.clone();
+        let mut order: Vec<usize> = (0..self.nodes.len()).collect();
+        order.sort_by_key(|&i| (!health[i].healthy, std::cmp::Reverse(health[i].slot)));
+        order
+    }
+}
+
+/// Whether `error` means the node is unreachable or failing, rather than
+/// rejecting the request the way every node would.
+fn is_node_failure(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::Io(_) => true,
+        ClientErrorKind::Reqwest(e) => e.status().map_or(true, |status| status.is_server_error()),
+        _ => false,
+    }
+}
+
+/// Whether `error` is a node reporting that `slot` was skipped or is missing
+/// from long-term storage, so there is no block to fetch.
+pub fn is_skipped_slot(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<ClientError>().map(ClientError::kind) {
+        Some(ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. })) => {
+            *code == JSON_RPC_SERVER_ERROR_SLOT_SKIPPED
+                || *code == JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_SLOT_SKIPPED
+        }
+        _ => false,
+    }
+}
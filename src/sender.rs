@@ -1,9 +1,8 @@
 use crate::block::SolanaBlock;
+use crate::rpc_pool::RpcPool;
 use anyhow::{anyhow, Result};
 use log::info;
 use serde_json;
-use solana_client::rpc_client::RpcClient;
-use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::instruction::Instruction;
 use solana_sdk::message::Message;
 use solana_sdk::pubkey::Pubkey;
@@ -12,10 +11,7 @@ use solana_sdk::transaction::Transaction;
 
 const MEMO_PROGRAM_ID: &str = "Memo111111111111111111111111111111111111111";
 
-pub fn send_block(rpc_url: &str, block: &SolanaBlock, keypair_path: &str) -> Result<()> {
-    info!("Connecting to Solana RPC at {}", rpc_url);
-    let client = RpcClient::new_with_commitment(rpc_url.to_string(), CommitmentConfig::confirmed());
-
+pub fn send_block(pool: &RpcPool, block: &SolanaBlock, keypair_path: &str) -> Result<()> {
     let keypair = read_keypair_file(keypair_path)
         .map_err(|e| anyhow!("Failed to read keypair file {}: {:?}", keypair_path, e))?;
     let payer = keypair.pubkey();
@@ -35,13 +31,13 @@ pub fn send_block(rpc_url: &str, block: &SolanaBlock, keypair_path: &str) -> Res
 
     let message = Message::new(&[memo_instruction], Some(&payer));
 
-    let recent_blockhash = client
-        .get_latest_blockhash()
+    let recent_blockhash = pool
+        .call(|client| client.get_latest_blockhash())
         .map_err(|e| anyhow!("Failed to get recent blockhash: {:?}", e))?;
     let transaction = Transaction::new(&[&keypair], message, recent_blockhash);
 
-    let signature = client
-        .send_and_confirm_transaction(&transaction)
+    let signature = pool
+        .call(|client| client.send_and_confirm_transaction(&transaction))
         .map_err(|e| anyhow!("Failed to send transaction: {:?}", e))?;
     info!(
         "Block sent successfully with transaction signature: {}",
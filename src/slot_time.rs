@@ -0,0 +1,149 @@
+use crate::rpc_pool::{is_skipped_slot, RpcPool};
+use anyhow::{anyhow, Result};
+use cached::{Cached, SizedCache};
+use log::info;
+use solana_sdk::clock::{Slot, UnixTimestamp};
+
+const BLOCK_TIME_CACHE_SIZE: usize = 4_096;
+/// How many consecutive slots may be skipped before a lookup gives up.
+const MAX_SKIPPED_SLOTS: u64 = 64;
+
+/// Parses an RFC3339 timestamp such as `2024-01-31T12:00:00Z`.
+pub fn parse_timestamp(value: &str) -> Result<UnixTimestamp> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|t| t.timestamp())
+        .map_err(|e| anyhow!("Invalid RFC3339 timestamp {}: {:?}", value, e))
+}
+
+/// Resolves wall-clock times to slots by binary search over `getBlockTime`.
+pub struct SlotTimeResolver<'a> {
+    pool: &'a RpcPool,
+    block_times: SizedCache<Slot, Option<UnixTimestamp>>,
+}
+
+impl<'a> SlotTimeResolver<'a> {
+    pub fn new(pool: &'a RpcPool) -> Self {
+        SlotTimeResolver {
+            pool,
+            block_times: SizedCache::with_size(BLOCK_TIME_CACHE_SIZE),
+        }
+    }
+
+    /// Returns the first slot whose block time is at or after `timestamp`.
+    pub fn first_slot_at_or_after(&mut self, timestamp: UnixTimestamp) -> Result<Slot> {
+        let (first, last) = self.slot_bounds()?;
+        if self.effective_time(last)?.1 < timestamp {
+            return Err(anyhow!(
+                "Timestamp {} is after the latest block at slot {}",
+                timestamp,
+                last
+            ));
+        }
+        let searched = self.search(first, last, timestamp)?;
+        let slot = self.effective_time(searched)?.0;
+        info!("Resolved timestamp {} to first slot {}", timestamp, slot);
+        Ok(slot)
+    }
+
+    /// Returns the last slot whose block time is at or before `timestamp`.
+    pub fn last_slot_at_or_before(&mut self, timestamp: UnixTimestamp) -> Result<Slot> {
+        let (first, last) = self.slot_bounds()?;
+        if self.effective_time(first)?.1 > timestamp {
+            return Err(anyhow!(
+                "Timestamp {} is before the first available block at slot {}",
+                timestamp,
+                first
+            ));
+        }
+        let slot = if self.effective_time(last)?.1 <= timestamp {
+            last
+        } else {
+            self.search(first, last, timestamp.saturating_add(1))? - 1
+        };
+        info!("Resolved timestamp {} to last slot {}", timestamp, slot);
+        Ok(slot)
+    }
+
+    /// Finds the smallest slot in `[low, high]` whose effective time is at
+    /// least `timestamp`, assuming `high` satisfies it.
+    fn search(&mut self, mut low: Slot, mut high: Slot, timestamp: UnixTimestamp) -> Result<Slot> {
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.effective_time(mid)?.1 >= timestamp {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        Ok(low)
+    }
+
+    fn slot_bounds(&self) -> Result<(Slot, Slot)> {
+        let first = self
+            .pool
+            .call(|client| client.get_first_available_block())
+            .map_err(|e| anyhow!("Failed to get first available block: {:?}", e))?;
+        let last = self
+            .pool
+            .call(|client| client.get_slot())
+            .map_err(|e| anyhow!("Failed to get current slot: {:?}", e))?;
+        Ok((first, last))
+    }
+
+    /// Returns the block time of `slot`, or of the next produced slot when
+    /// `slot` was skipped, so that times are monotonic over all slots.
+    fn effective_time(&mut self, slot: Slot) -> Result<(Slot, UnixTimestamp)> {
+        for candidate in slot..slot.saturating_add(MAX_SKIPPED_SLOTS) {
+            if let Some(time) = self.block_time(candidate)? {
+                return Ok((candidate, time));
+            }
+        }
+        Err(anyhow!(
+            "No block time found within {} slots of slot {}",
+            MAX_SKIPPED_SLOTS,
+            slot
+        ))
+    }
+
+    /// Returns `None` for a skipped slot. Other errors are not cached, so a
+    /// later lookup asks again.
+    fn block_time(&mut self, slot: Slot) -> Result<Option<UnixTimestamp>> {
+        if let Some(time) = self.block_times.cache_get(&slot) {
+            return Ok(*time);
+        }
+        let time = match self.pool.call(|client| client.get_block_time(slot)) {
+            Ok(time) => Some(time),
+            Err(e) if is_skipped_slot(&e) => None,
+            Err(e) => {
+                return Err(anyhow!(
+                    "Failed to get block time of slot {}: {:?}",
+                    slot,
+                    e
+                ))
+            }
+        };
+        self.block_times.cache_set(slot, time);
+        Ok(time)
+    }
+}
+
+/// Turns optional `--since`/`--until` times into a slot window, falling
+/// back to the explicit `start`/`end` slots when no time is given.
+pub fn resolve_window(
+    pool: &RpcPool,
+    start: Option<Slot>,
+    end: Option<Slot>,
+    since: Option<&str>,
+    until: Option<&str>,
+) -> Result<(Option<Slot>, Option<Slot>)> {
+    let mut resolver = SlotTimeResolver::new(pool);
+    let start = match since {
+        Some(since) => Some(resolver.first_slot_at_or_after(parse_timestamp(since)?)?),
+        None => start,
+    };
+    let end = match until {
+        Some(until) => Some(resolver.last_slot_at_or_before(parse_timestamp(until)?)?),
+        None => end,
+    };
+    Ok((start, end))
+}
@@ -0,0 +1,94 @@
+use crate::block::SolanaBlock;
+use anyhow::{anyhow, Result};
+use log::info;
+use solana_sdk::clock::Slot;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// On-disk record of fetched blocks, backfill progress and slots that
+/// could not be fetched.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct BlockStore {
+    #[serde(skip)]
+    path: PathBuf,
+    pub blocks: BTreeMap<Slot, SolanaBlock>,
+    /// Highest slot the backfill has fully processed, fetched or failed.
+    pub checkpoint: Option<Slot>,
+    /// The `(start, end)` range of the backfill that recorded `checkpoint`.
+    #[serde(default)]
+    pub checkpoint_range: Option<(Slot, Slot)>,
+    pub failed_slots: BTreeSet<Slot>,
+}
+
+impl BlockStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut store = if path.exists() {
+            let data = fs::read_to_string(path)
+                .map_err(|e| anyhow!("Failed to read store {}: {:?}", path.display(), e))?;
+            serde_json::from_str::<BlockStore>(&data)
+                .map_err(|e| anyhow!("Failed to parse store {}: {:?}", path.display(), e))?
+        } else {
+            BlockStore::default()
+        };
+        store.path = path.to_path_buf();
+        info!(
+            "Opened store {} with {} blocks, checkpoint: {:?}, failed slots: {}",
+            path.display(),
+            store.blocks.len(),
+            store.checkpoint,
+            store.failed_slots.len()
+        );
+        Ok(store)
+    }
+
+    /// Writes the store to a temporary file and renames it into place, so a
+    /// process killed mid-write never leaves a truncated store behind.
+    pub fn save(&self) -> Result<()> {
+        let data = serde_json::to_string(self)
+            .map_err(|e| anyhow!("Failed to serialize store: {:?}", e))?;
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, data)
+            .map_err(|e| anyhow!("Failed to write store {}: {:?}", tmp_path.display(), e))?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| anyhow!("Failed to replace store {}: {:?}", self.path.display(), e))?;
+        Ok(())
+    }
+
+    pub fn insert_block(&mut self, block: SolanaBlock) {
+        self.failed_slots.remove(&block.slot);
+        self.blocks.insert(block.slot, block);
+    }
+
+    pub fn record_failure(&mut self, slot: Slot) {
+        self.failed_slots.insert(slot);
+    }
+
+    /// Records that `slot` has no block, so it is no longer retried.
+    pub fn record_skipped(&mut self, slot: Slot) {
+        self.failed_slots.remove(&slot);
+    }
+
+    /// Records that the backfill of `range` has processed every slot up to
+    /// `slot`, discarding progress recorded for any other range.
+    pub fn set_checkpoint(&mut self, range: (Slot, Slot), slot: Slot) {
+        if self.checkpoint_range != Some(range) {
+            self.checkpoint_range = Some(range);
+            self.checkpoint = None;
+        }
+        self.checkpoint = Some(self.checkpoint.map_or(slot, |c| c.max(slot)));
+    }
+
+    /// Returns the checkpoint if it was recorded by a backfill of `range`.
+    pub fn checkpoint_for(&self, range: (Slot, Slot)) -> Option<Slot> {
+        if self.checkpoint_range != Some(range) {
+            return None;
+        }
+        self.checkpoint
+            .filter(|checkpoint| (range.0..=range.1).contains(checkpoint))
+    }
+
+    pub fn into_blocks(self) -> Vec<SolanaBlock> {
+        self.blocks.into_values().collect()
+    }
+}
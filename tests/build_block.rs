@@ -0,0 +1,66 @@
+use proptest::prelude::*;
+use solana_block_builder::builder::build_block;
+
+#[derive(serde::Deserialize)]
+struct GoldenVector {
+    slot: u64,
+    previous_hash: Option<String>,
+    blockhash: String,
+}
+
+#[test]
+fn build_block_matches_golden_vectors() {
+    let vectors: Vec<GoldenVector> =
+        serde_json::from_str(include_str!("vectors/build_block.json")).unwrap();
+    assert!(!vectors.is_empty());
+
+    for vector in vectors {
+        let block = build_block(vector.slot, vector.previous_hash.as_deref()).unwrap();
+        assert_eq!(block.slot, vector.slot);
+        assert_eq!(
+            block.blockhash, vector.blockhash,
+            "slot {} with previous {:?}",
+            vector.slot, vector.previous_hash
+        );
+    }
+}
+
+proptest! {
+    #[test]
+    fn build_block_is_deterministic(slot: u64, previous in proptest::option::of(".*")) {
+        let first = build_block(slot, previous.as_deref()).unwrap();
+        let second = build_block(slot, previous.as_deref()).unwrap();
+        prop_assert_eq!(first.slot, slot);
+        prop_assert_eq!(first.blockhash, second.blockhash);
+    }
+
+    #[test]
+    fn build_block_hash_is_lowercase_hex_sha256(slot: u64, previous in proptest::option::of(".*")) {
+        let block = build_block(slot, previous.as_deref()).unwrap();
+        prop_assert_eq!(block.blockhash.len(), 64);
+        prop_assert!(block.blockhash.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c)));
+    }
+
+    #[test]
+    fn build_block_depends_on_slot(a: u64, b: u64, previous in proptest::option::of(".*")) {
+        prop_assume!(a != b);
+        let first = build_block(a, previous.as_deref()).unwrap();
+        let second = build_block(b, previous.as_deref()).unwrap();
+        prop_assert_ne!(first.blockhash, second.blockhash);
+    }
+
+    #[test]
+    fn build_block_depends_on_previous_hash(slot: u64, a in "[0-9a-f]{64}", b in "[0-9a-f]{64}") {
+        prop_assume!(a != b);
+        let first = build_block(slot, Some(&a)).unwrap();
+        let second = build_block(slot, Some(&b)).unwrap();
+        prop_assert_ne!(first.blockhash, second.blockhash);
+    }
+
+    #[test]
+    fn build_block_distinguishes_genesis_from_previous(slot: u64, previous in "[0-9a-f]{64}") {
+        let genesis = build_block(slot, None).unwrap();
+        let chained = build_block(slot, Some(&previous)).unwrap();
+        prop_assert_ne!(genesis.blockhash, chained.blockhash);
+    }
+}
@@ -0,0 +1,337 @@
+//! In-process fake Solana JSON-RPC server for offline integration tests.
+
+#![allow(dead_code)]
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Value};
+use solana_sdk::hash::Hash;
+use solana_sdk::signature::Signature;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// JSON-RPC error code returned by validators for skipped or missing slots.
+pub const SLOT_SKIPPED: i64 = -32007;
+/// JSON-RPC error code returned when a block exists but is not available yet.
+pub const BLOCK_NOT_AVAILABLE: i64 = -32004;
+
+/// A scripted reply for a single call to a method.
+#[derive(Clone, Debug)]
+pub enum MockReply {
+    Result(Value),
+    RpcError { code: i64, message: String },
+    HttpStatus(u16),
+}
+
+#[derive(Default)]
+struct MockState {
+    scripted: HashMap<String, VecDeque<MockReply>>,
+    blocks: HashMap<u64, String>,
+    unavailable_blocks: HashSet<u64>,
+    block_times: HashMap<u64, i64>,
+    slot: u64,
+    calls: Vec<(String, Value)>,
+}
+
+pub struct MockRpcServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    exit: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockRpcServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(MockState {
+            slot: 1_000,
+            ..MockState::default()
+        }));
+        let exit = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let state = state.clone();
+            let exit = exit.clone();
+            thread::spawn(move || {
+                while !exit.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            // Accepted streams inherit the listener's
+                            // non-blocking mode on macOS and the BSDs.
+                            stream.set_nonblocking(false).unwrap();
+                            let state = state.clone();
+                            thread::spawn(move || serve_connection(stream, &state));
+                        }
+                        Err(_) => thread::sleep(Duration::from_millis(5)),
+                    }
+                }
+            })
+        };
+
+        MockRpcServer {
+            addr,
+            state,
+            exit,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Makes `getBlock` for `slot` return a block with `blockhash`.
+    pub fn set_block(&self, slot: u64, blockhash: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.unavailable_blocks.remove(&slot);
+        state.blocks.insert(slot, blockhash.to_string());
+    }
+
+    /// Makes `getBlock` for `slot` fail with a block-not-available error
+    /// until a block is set for it.
+    pub fn set_block_unavailable(&self, slot: u64) {
+        self.state.lock().unwrap().unavailable_blocks.insert(slot);
+    }
+
+    /// Makes `getBlockTime` for `slot` return `timestamp`.
+    pub fn set_block_time(&self, slot: u64, timestamp: i64) {
+        self.state
+            .lock()
+            .unwrap()
+            .block_times
+            .insert(slot, timestamp);
+    }
+
+    /// Sets the slot reported by `getSlot`.
+    pub fn set_slot(&self, slot: u64) {
+        self.state.lock().unwrap().slot = slot;
+    }
+
+    /// Queues a one-shot reply for the next call to `method`, ahead of the
+    /// default behaviour.
+    pub fn push_reply(&self, method: &str, reply: MockReply) {
+        self.state
+            .lock()
+            .unwrap()
+            .scripted
+            .entry(method.to_string())
+            .or_default()
+            .push_back(reply);
+    }
+
+    /// Makes the next `times` calls to `method` fail with an HTTP 500.
+    pub fn fail_next(&self, method: &str, times: usize) {
+        for _ in 0..times {
+            self.push_reply(method, MockReply::HttpStatus(500));
+        }
+    }
+
+    /// Returns the params of every call made to `method`, in order.
+    pub fn calls(&self, method: &str) -> Vec<Value> {
+        self.state
+            .lock()
+            .unwrap()
+            .calls
+            .iter()
+            .filter(|(m, _)| m == method)
+            .map(|(_, params)| params.clone())
+            .collect()
+    }
+}
+
+impl Drop for MockRpcServer {
+    fn drop(&mut self) {
+        self.exit.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Returns a fresh path under the system temp dir for a test's files.
+pub fn temp_path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("building-solana-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn serve_connection(stream: TcpStream, state: &Arc<Mutex<MockState>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    loop {
+        let mut content_length = 0;
+        let mut saw_request_line = false;
+        loop {
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                if saw_request_line {
+                    break;
+                }
+                continue;
+            }
+            saw_request_line = true;
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        let (status, response) = handle_request(&request, state);
+        let response = response.to_string();
+        let reply = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            if status == 200 { "OK" } else { "Error" },
+            response.len(),
+            response
+        );
+        if writer.write_all(reply.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+fn handle_request(request: &Value, state: &Arc<Mutex<MockState>>) -> (u16, Value) {
+    let id = request["id"].clone();
+    let method = request["method"].as_str().unwrap_or_default().to_string();
+    let params = request["params"].clone();
+
+    let mut state = state.lock().unwrap();
+    state.calls.push((method.clone(), params.clone()));
+    let reply = match state.scripted.get_mut(&method).and_then(|q| q.pop_front()) {
+        Some(reply) => reply,
+        None => default_reply(&method, &params, &state),
+    };
+
+    match reply {
+        MockReply::Result(result) => (200, json!({"jsonrpc": "2.0", "id": id, "result": result})),
+        MockReply::RpcError { code, message } => (
+            200,
+            json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}}),
+        ),
+        MockReply::HttpStatus(status) => (status, json!({})),
+    }
+}
+
+fn default_reply(method: &str, params: &Value, state: &MockState) -> MockReply {
+    let context = json!({"slot": state.slot});
+    match method {
+        "getVersion" => MockReply::Result(json!({"solana-core": "1.17.0", "feature-set": 0})),
+        "getSlot" => MockReply::Result(json!(state.slot)),
+        "getBlock" => {
+            let slot = params[0].as_u64().unwrap_or_default();
+            if state.unavailable_blocks.contains(&slot) {
+                return MockReply::RpcError {
+                    code: BLOCK_NOT_AVAILABLE,
+                    message: format!("Block not available for slot {}", slot),
+                };
+            }
+            match state.blocks.get(&slot) {
+                Some(blockhash) => MockReply::Result(json!({
+                    "blockhash": blockhash,
+                    "previousBlockhash": Hash::default().to_string(),
+                    "parentSlot": slot.saturating_sub(1),
+                    "transactions": [],
+                    "rewards": [],
+                    "blockTime": null,
+                    "blockHeight": null,
+                })),
+                None => MockReply::RpcError {
+                    code: SLOT_SKIPPED,
+                    message: format!("Slot {} was skipped, or missing in long-term storage", slot),
+                },
+            }
+        }
+        "getBlockTime" => {
+            let slot = params[0].as_u64().unwrap_or_default();
+            match state.block_times.get(&slot) {
+                Some(timestamp) => MockReply::Result(json!(timestamp)),
+                None => MockReply::RpcError {
+                    code: SLOT_SKIPPED,
+                    message: format!("Slot {} was skipped, or missing in long-term storage", slot),
+                },
+            }
+        }
+        "getFirstAvailableBlock" => {
+            MockReply::Result(json!(state.block_times.keys().min().copied().unwrap_or(0)))
+        }
+        "getLatestBlockhash" => MockReply::Result(json!({
+            "context": context,
+            "value": {
+                "blockhash": Hash::new_unique().to_string(),
+                "lastValidBlockHeight": state.slot + 150,
+            },
+        })),
+        "sendTransaction" => match first_signature(params) {
+            Some(signature) => MockReply::Result(json!(signature.to_string())),
+            None => MockReply::RpcError {
+                code: -32602,
+                message: "invalid transaction".to_string(),
+            },
+        },
+        "getSignatureStatuses" => {
+            let statuses: Vec<Value> = params[0]
+                .as_array()
+                .map(|sigs| {
+                    sigs.iter()
+                        .map(|_| {
+                            json!({
+                                "slot": state.slot,
+                                "confirmations": null,
+                                "err": null,
+                                "status": {"Ok": null},
+                                "confirmationStatus": "finalized",
+                            })
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            MockReply::Result(json!({"context": context, "value": statuses}))
+        }
+        "simulateTransaction" => MockReply::Result(json!({
+            "context": context,
+            "value": {
+                "err": null,
+                "logs": [],
+                "accounts": null,
+                "unitsConsumed": 0,
+                "returnData": null,
+            },
+        })),
+        _ => MockReply::RpcError {
+            code: -32601,
+            message: format!("Method not found: {}", method),
+        },
+    }
+}
+
+/// Reads the first signature out of a base64 wire transaction: a
+/// compact-u16 signature count followed by 64-byte signatures.
+fn first_signature(params: &Value) -> Option<Signature> {
+    let bytes = STANDARD.decode(params[0].as_str()?).ok()?;
+    let mut offset = 0;
+    while bytes.get(offset)? & 0x80 != 0 {
+        offset += 1;
+    }
+    let start = offset + 1;
+    Some(Signature::new(bytes.get(start..start + 64)?))
+}
@@ -0,0 +1,372 @@
+mod common;
+
+use common::{temp_path, MockReply, MockRpcServer};
+use solana_block_builder::backfill::{backfill, retry_failed, BackfillConfig};
+use solana_block_builder::block::{fetch_block, SolanaBlock};
+use solana_block_builder::builder::build_block;
+use solana_block_builder::organizer::organize_blocks;
+use solana_block_builder::rpc_pool::RpcPool;
+use solana_block_builder::sender::send_block;
+use solana_block_builder::slot_time::{parse_timestamp, resolve_window, SlotTimeResolver};
+use solana_block_builder::store::BlockStore;
+use solana_sdk::signature::{write_keypair_file, Keypair};
+use std::process::Command;
+
+fn pool_for(servers: &[&MockRpcServer]) -> RpcPool {
+    let urls: Vec<String> = servers.iter().map(|s| s.url()).collect();
+    RpcPool::new(&urls).unwrap()
+}
+
+#[test]
+fn fetch_block_returns_blockhash_from_rpc() {
+    let server = MockRpcServer::start();
+    server.set_block(42, "hash-42");
+
+    let block = fetch_block(&pool_for(&[&server]), 42).unwrap();
+
+    assert_eq!(block.slot, 42);
+    assert_eq!(block.blockhash, "hash-42");
+}
+
+#[test]
+fn fetch_block_fails_over_to_next_node() {
+    let primary = MockRpcServer::start();
+    let backup = MockRpcServer::start();
+    primary.set_slot(2_000);
+    primary.fail_next("getBlock", 1);
+    backup.set_block(7, "hash-7");
+
+    let block = fetch_block(&pool_for(&[&primary, &backup]), 7).unwrap();
+
+    assert_eq!(block.blockhash, "hash-7");
+    assert_eq!(primary.calls("getBlock").len(), 1);
+    assert_eq!(backup.calls("getBlock").len(), 1);
+}
+
+#[test]
+fn rpc_errors_are_returned_without_failing_over() {
+    let primary = MockRpcServer::start();
+    let backup = MockRpcServer::start();
+    primary.set_slot(2_000);
+    backup.set_block(7, "hash-7");
+    let pool = pool_for(&[&primary, &backup]);
+
+    // The primary answers with a skipped-slot error, which is a valid answer
+    // rather than a node failure.
+    assert!(fetch_block(&pool, 7).is_err());
+    assert!(backup.calls("getBlock").is_empty());
+
+    // The primary is still routed to first.
+    primary.set_block(8, "hash-8");
+    assert_eq!(fetch_block(&pool, 8).unwrap().blockhash, "hash-8");
+    assert_eq!(primary.calls("getBlock").len(), 2);
+}
+
+#[test]
+fn backfill_records_failures_and_retries_them() {
+    let server = MockRpcServer::start();
+    for slot in [10, 11, 13, 14] {
+        server.set_block(slot, &format!("hash-{}", slot));
+    }
+    server.set_block_unavailable(12);
+    let pool = pool_for(&[&server]);
+    let path = temp_path("backfill.json");
+    let config = BackfillConfig {
+        start: 10,
+        end: 14,
+        checkpoint_interval: 2,
+        max_retries: 0,
+    };
+
+    let mut store = BlockStore::open(&path).unwrap();
+    let summary = backfill(&pool, &config, &mut store).unwrap();
+    assert_eq!(summary.fetched, 4);
+    assert_eq!(summary.skipped, 0);
+    assert_eq!(summary.failed, 1);
+
+    // A restarted backfill resumes after the checkpoint and fetches nothing.
+    let mut store = BlockStore::open(&path).unwrap();
+    assert_eq!(store.checkpoint, Some(14));
+    assert!(store.failed_slots.contains(&12));
+    let calls_before = server.calls("getBlock").len();
+    let summary = backfill(&pool, &config, &mut store).unwrap();
+    assert_eq!(summary.fetched, 0);
+    assert_eq!(server.calls("getBlock").len(), calls_before);
+
+    server.set_block(12, "hash-12");
+    let summary = retry_failed(&pool, 0, &mut store).unwrap();
+    assert_eq!(summary.fetched, 1);
+    assert!(store.failed_slots.is_empty());
+    assert_eq!(store.blocks.len(), 5);
+}
+
+#[test]
+fn backfill_counts_skipped_slots_without_retrying_them() {
+    let server = MockRpcServer::start();
+    for slot in [20, 22] {
+        server.set_block(slot, &format!("hash-{}", slot));
+    }
+    let pool = pool_for(&[&server]);
+    let mut store = BlockStore::open(&temp_path("backfill-skipped.json")).unwrap();
+    store.record_failure(21);
+    let config = BackfillConfig {
+        start: 20,
+        end: 22,
+        checkpoint_interval: 10,
+        max_retries: 3,
+    };
+
+    let summary = backfill(&pool, &config, &mut store).unwrap();
+    assert_eq!(summary.fetched, 2);
+    assert_eq!(summary.skipped, 1);
+    assert_eq!(summary.failed, 0);
+    assert!(store.failed_slots.is_empty());
+    assert_eq!(store.checkpoint, Some(22));
+    // The skipped slot is asked for once rather than retried with backoff.
+    let calls = server.calls("getBlock");
+    assert_eq!(calls.iter().filter(|params| params[0] == 21).count(), 1);
+}
+
+#[test]
+fn backfill_of_a_different_range_ignores_the_checkpoint() {
+    let server = MockRpcServer::start();
+    for slot in 8..=14 {
+        server.set_block(slot, &format!("hash-{}", slot));
+    }
+    let pool = pool_for(&[&server]);
+    let mut store = BlockStore::open(&temp_path("backfill-ranges.json")).unwrap();
+    let config = |start, end| BackfillConfig {
+        start,
+        end,
+        checkpoint_interval: 2,
+        max_retries: 0,
+    };
+
+    backfill(&pool, &config(10, 14), &mut store).unwrap();
+    assert_eq!(store.checkpoint, Some(14));
+
+    // An overlapping range that starts earlier is fetched from its start
+    // rather than resumed after the other range's checkpoint.
+    let summary = backfill(&pool, &config(8, 12), &mut store).unwrap();
+    assert_eq!(summary.fetched, 5);
+    assert_eq!(store.checkpoint, Some(12));
+    assert_eq!(store.checkpoint_range, Some((8, 12)));
+}
+
+#[test]
+fn fetch_organize_and_build_chain() {
+    let server = MockRpcServer::start();
+    for slot in [3, 1, 2] {
+        server.set_block(slot, &format!("hash-{}", slot));
+    }
+    let pool = pool_for(&[&server]);
+
+    let fetched = [3, 1, 2]
+        .iter()
+        .map(|&slot| fetch_block(&pool, slot).unwrap())
+        .collect();
+    let organized = organize_blocks(fetched).unwrap();
+    assert_eq!(
+        organized.iter().map(|b| b.slot).collect::<Vec<_>>(),
+        vec![1, 2, 3]
+    );
+
+    let mut previous: Option<String> = None;
+    for block in &organized {
+        let built = build_block(block.slot, previous.as_deref()).unwrap();
+        assert_eq!(built.slot, block.slot);
+        previous = Some(built.blockhash);
+    }
+    assert_eq!(previous.unwrap().len(), 64);
+}
+
+#[test]
+fn time_window_resolves_to_slots() {
+    let server = MockRpcServer::start();
+    let base = parse_timestamp("2024-01-01T00:00:00Z").unwrap();
+    // Slots 100..=200 produced one per second, with slot 150 skipped.
+    for slot in 100..=200 {
+        if slot != 150 {
+            server.set_block_time(slot, base + (slot as i64 - 100));
+        }
+    }
+    server.set_slot(200);
+    let pool = pool_for(&[&server]);
+
+    let window = resolve_window(
+        &pool,
+        None,
+        None,
+        Some("2024-01-01T00:00:10Z"),
+        Some("2024-01-01T00:00:49Z"),
+    )
+    .unwrap();
+    assert_eq!(window, (Some(110), Some(149)));
+
+    // A window starting on the skipped slot begins at the next produced one.
+    let window =
+        resolve_window(&pool, None, Some(180), Some("2024-01-01T00:00:50Z"), None).unwrap();
+    assert_eq!(window, (Some(151), Some(180)));
+
+    assert!(resolve_window(&pool, None, None, Some("2024-01-02T00:00:00Z"), None).is_err());
+}
+
+#[test]
+fn transient_block_time_errors_are_not_cached_as_skipped() {
+    let server = MockRpcServer::start();
+    let base = parse_timestamp("2024-01-01T00:00:00Z").unwrap();
+    for slot in 100..=200 {
+        server.set_block_time(slot, base + (slot as i64 - 100));
+    }
+    server.set_slot(200);
+    let pool = pool_for(&[&server]);
+    let mut resolver = SlotTimeResolver::new(&pool);
+    let timestamp = parse_timestamp("2024-01-01T00:00:10Z").unwrap();
+
+    server.fail_next("getBlockTime", 1);
+    assert!(resolver.first_slot_at_or_after(timestamp).is_err());
+    assert_eq!(resolver.first_slot_at_or_after(timestamp).unwrap(), 110);
+}
+
+#[test]
+fn send_block_submits_signed_memo_transaction() {
+    let server = MockRpcServer::start();
+    let keypair_path = temp_path("send-keypair.json");
+    write_keypair_file(&Keypair::new(), &keypair_path).unwrap();
+    let block = build_block(5, None).unwrap();
+
+    send_block(
+        &pool_for(&[&server]),
+        &block,
+        keypair_path.to_str().unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(server.calls("getLatestBlockhash").len(), 1);
+    assert_eq!(server.calls("sendTransaction").len(), 1);
+    assert!(!server.calls("getSignatureStatuses").is_empty());
+}
+
+#[test]
+fn send_block_surfaces_rpc_errors() {
+    let server = MockRpcServer::start();
+    let keypair_path = temp_path("send-error-keypair.json");
+    write_keypair_file(&Keypair::new(), &keypair_path).unwrap();
+    server.push_reply(
+        "sendTransaction",
+        MockReply::RpcError {
+            code: -32002,
+            message: "Transaction simulation failed".to_string(),
+        },
+    );
+    let block = build_block(5, None).unwrap();
+
+    let result = send_block(
+        &pool_for(&[&server]),
+        &block,
+        keypair_path.to_str().unwrap(),
+    );
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn cli_fetch_writes_store() {
+    let server = MockRpcServer::start();
+    server.set_block(20, "hash-20");
+    server.set_block(21, "hash-21");
+    let path = temp_path("cli-fetch.json");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_building-solana"))
+        .args([
+            "--rpc-url",
+            &server.url(),
+            "fetch",
+            "--start",
+            "20",
+            "--end",
+            "21",
+        ])
+        .arg("--store")
+        .arg(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let store = BlockStore::open(&path).unwrap();
+    assert_eq!(store.blocks[&21].blockhash, "hash-21");
+}
+
+#[test]
+fn cli_fetch_fails_with_non_zero_exit() {
+    let server = MockRpcServer::start();
+    server.set_block_unavailable(30);
+    let path = temp_path("cli-fetch-error.json");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_building-solana"))
+        .args(["--rpc-url", &server.url(), "fetch", "--start", "30"])
+        .arg("--store")
+        .arg(&path)
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn cli_organize_prints_store_in_slot_order() {
+    let server = MockRpcServer::start();
+    let path = temp_path("cli-organize.json");
+    let mut store = BlockStore::open(&path).unwrap();
+    for slot in [7, 5, 6, 9] {
+        store.insert_block(SolanaBlock {
+            slot,
+            blockhash: format!("hash-{}", slot),
+        });
+    }
+    store.save().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_building-solana"))
+        .args(["--rpc-url", &server.url(), "organize", "--end", "7"])
+        .arg("--store")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let printed: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.starts_with("Slot: "))
+        .collect();
+    assert_eq!(
+        printed,
+        vec![
+            "Slot: 5, Blockhash: hash-5",
+            "Slot: 6, Blockhash: hash-6",
+            "Slot: 7, Blockhash: hash-7",
+        ]
+    );
+}
+
+#[test]
+fn cli_send_uses_rpc_url() {
+    let server = MockRpcServer::start();
+    let keypair_path = temp_path("cli-send-keypair.json");
+    write_keypair_file(&Keypair::new(), &keypair_path).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_building-solana"))
+        .args([
+            "--rpc-url",
+            &server.url(),
+            "send",
+            "--slot",
+            "9",
+            "--keypair",
+        ])
+        .arg(&keypair_path)
+        .output()
+        .unwrap();
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Block sent successfully."));
+    assert_eq!(server.calls("sendTransaction").len(), 1);
+}
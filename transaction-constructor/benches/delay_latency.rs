@@ -0,0 +1,194 @@
+//! Compares release latency of the shared delay queue against the previous
+//! per-thread buffer design.
+//!
+//! Run with `cargo bench --bench delay_latency`.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    thread::{self, Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use blocks::{
+    metrics::LogMetricsSink,
+    packet::{Packet, PacketBatch},
+};
+use builder_block::health::HealthState;
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use transaction_constructor::{
+    delay_policy::FixedDelay,
+    fowardDelay::{start_forward_and_delay_thread, ForwarderConfig, ForwarderStats},
+};
+
+const PACKET_DELAY_MS: u32 = 50;
+const NUM_THREADS: u64 = 4;
+const NUM_PACKETS: u64 = 5_000;
+const SEND_INTERVAL: Duration = Duration::from_micros(200);
+
+/// The original design: each worker owns a private buffer and only releases
+/// from it between receives.
+fn start_per_thread_buffers(
+    verified_receiver: Receiver<PacketBatch>,
+    delay_packet_sender: Sender<PacketBatch>,
+    packet_delay_ms: u32,
+    num_threads: u64,
+    exit: &Arc<AtomicBool>,
+) -> Vec<JoinHandle<()>> {
+    const SLEEP_DURATION: Duration = Duration::from_millis(5);
+    let packet_delay = Duration::from_millis(packet_delay_ms as u64);
+
+    (0..num_threads)
+        .map(|thread_id| {
+            let verified_receiver = verified_receiver.clone();
+            let delay_packet_sender = delay_packet_sender.clone();
+            let exit = exit.clone();
+            Builder::new()
+                .name(format!("legacy_forwarder_{thread_id}"))
+                .spawn(move || {
+                    let mut buffered_packets: VecDeque<(Instant, Packet)> = VecDeque::new();
+                    while !exit.load(Ordering::Relaxed) {
+                        match verified_receiver.recv_timeout(SLEEP_DURATION) {
+                            Ok(batch) => buffered_packets
+                                .extend(batch.into_iter().map(|packet| (Instant::now(), packet))),
+                            Err(RecvTimeoutError::Timeout) => {}
+                            Err(RecvTimeoutError::Disconnected) => break,
+                        }
+                        while let Some((timestamp, _)) = buffered_packets.front() {
+                            if timestamp.elapsed() < packet_delay {
+                                break;
+                            }
+                            let (_, packet) = buffered_packets.pop_front().unwrap();
+                            let _ = delay_packet_sender.send(PacketBatch::new(vec![packet]));
+                        }
+                    }
+                })
+                .unwrap()
+        })
+        .collect()
+}
+
+struct Report {
+    latencies_us: Vec<u64>,
+    reordered: u64,
+}
+
+/// Sends `NUM_PACKETS` tagged packets at a steady rate and measures how long
+/// after their intended release time each one arrives.
+fn run(
+    start: impl FnOnce(
+        Receiver<PacketBatch>,
+        Sender<PacketBatch>,
+        &Arc<AtomicBool>,
+    ) -> Vec<JoinHandle<()>>,
+) -> Report {
+    let exit = Arc::new(AtomicBool::new(false));
+    let (verified_sender, verified_receiver) = unbounded();
+    let (delay_sender, delay_receiver) = unbounded::<PacketBatch>();
+    let threads = start(verified_receiver, delay_sender, &exit);
+
+    let epoch = Instant::now();
+    let producer = thread::spawn(move || {
+        for i in 0..NUM_PACKETS {
+            let sent_us = epoch.elapsed().as_micros() as u64;
+            let mut packet = i.to_le_bytes().to_vec();
+            packet.extend_from_slice(&sent_us.to_le_bytes());
+            let packet = Packet::new(packet, "127.0.0.1:0".parse().unwrap(), false);
+            verified_sender
+                .send(PacketBatch::new(vec![packet]))
+                .unwrap();
+            thread::sleep(SEND_INTERVAL);
+        }
+        verified_sender
+    });
+
+    let delay_us = PACKET_DELAY_MS as u64 * 1_000;
+    let mut latencies_us = Vec::with_capacity(NUM_PACKETS as usize);
+    let mut reordered = 0;
+    let mut last_index = None;
+    while latencies_us.len() < NUM_PACKETS as usize {
+        let batch = delay_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("packet lost");
+        let received_us = epoch.elapsed().as_micros() as u64;
+        for packet in batch.iter() {
+            let data = packet.data();
+            let index = u64::from_le_bytes(data[..8].try_into().unwrap());
+            let sent_us = u64::from_le_bytes(data[8..16].try_into().unwrap());
+            latencies_us.push(received_us.saturating_sub(sent_us + delay_us));
+            if last_index.map_or(false, |last| index < last) {
+                reordered += 1;
+            }
+            last_index = Some(index);
+        }
+    }
+
+    let _verified_sender = producer.join().unwrap();
+    exit.store(true, Ordering::Relaxed);
+    for t in threads {
+        t.join().unwrap();
+    }
+    latencies_us.sort_unstable();
+    Report {
+        latencies_us,
+        reordered,
+    }
+}
+
+fn print_report(name: &str, report: &Report) {
+    let percentile = |p: f64| {
+        let index = ((report.latencies_us.len() - 1) as f64 * p).round() as usize;
+        report.latencies_us[index]
+    };
+    println!(
+        "{name:>18}: jitter p50 {}us, p90 {}us, p99 {}us, max {}us, reordered {}",
+        percentile(0.50),
+        percentile(0.90),
+        percentile(0.99),
+        report.latencies_us.last().unwrap(),
+        report.reordered,
+    );
+}
+
+fn main() {
+    println!(
+        "{NUM_PACKETS} packets, {NUM_THREADS} threads, {PACKET_DELAY_MS}ms delay, one packet every {SEND_INTERVAL:?}"
+    );
+
+    let per_thread = run(|verified_receiver, delay_sender, exit| {
+        start_per_thread_buffers(
+            verified_receiver,
+            delay_sender,
+            PACKET_DELAY_MS,
+            NUM_THREADS,
+            exit,
+        )
+    });
+    print_report("per-thread buffers", &per_thread);
+
+    let shared = run(|verified_receiver, delay_sender, exit| {
+        let (block_engine_sender, _block_engine_receiver) = tokio::sync::mpsc::channel(1);
+        start_forward_and_delay_thread(
+            verified_receiver,
+            delay_sender,
+            block_engine_sender,
+            Arc::new(FixedDelay(Duration::from_millis(PACKET_DELAY_MS as u64))),
+            Arc::new(RwLock::new(HealthState::Healthy)),
+            ForwarderConfig {
+                num_threads: NUM_THREADS,
+                disable_mempool: true,
+                queue_capacity: NUM_PACKETS as usize,
+                dedup: None,
+                ..ForwarderConfig::default()
+            },
+            &Arc::new(ForwarderStats::default()),
+            Arc::new(LogMetricsSink),
+            exit,
+        )
+        .expect("Failed to start forwarder")
+    });
+    print_report("shared queue", &shared);
+}
@@ -0,0 +1,143 @@
+//! Feeds a packet capture recorded with `--capture-path` back through the
+//! forward and delay stage, at the original pace or faster, and reports
+//! what reached validators and the block engine.
+
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use blocks::{block_relayer::EngineRelayerHandler, metrics::LogMetricsSink};
+use builder_block::health::HealthState;
+use clap::Parser;
+use env_logger::Env;
+use log::info;
+use transaction_constructor::{
+    capture::CaptureReader,
+    dedup::DedupConfig,
+    delay_policy::FixedDelay,
+    fowardDelay::{start_forward_and_delay_thread, ForwarderConfig, ForwarderStats},
+};
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Capture file to replay.
+    #[arg(long)]
+    capture_path: PathBuf,
+
+    /// Playback speed relative to the capture, e.g. 10 replays ten times
+    /// faster; 0 sends every batch as fast as possible.
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+
+    #[arg(long, default_value_t = 200)]
+    packet_delay_ms: u64,
+
+    #[arg(long, default_value_t = 1)]
+    forwarder_threads: u64,
+
+    /// 0 disables deduplication.
+    #[arg(long, default_value_t = 2)]
+    dedup_ttl_secs: u64,
+
+    #[arg(long, default_value_t = false)]
+    disable_mempool: bool,
+}
+
+fn main() -> Result<(), String> {
+    env_logger::Builder::from_env(Env::new().default_filter_or("info")).init();
+    let args = Args::parse();
+    if args.speed < 0.0 {
+        return Err("--speed must not be negative".to_string());
+    }
+
+    let capture = CaptureReader::open(&args.capture_path).map_err(|e| {
+        format!(
+            "Failed to open capture {}: {e}",
+            args.capture_path.display()
+        )
+    })?;
+
+    let exit = Arc::new(AtomicBool::new(false));
+    let (verified_sender, verified_receiver) = crossbeam_channel::unbounded();
+    let (delay_packet_sender, delay_packet_receiver) = crossbeam_channel::unbounded();
+    let (engine_sender, mut engine_receiver) =
+        tokio::sync::mpsc::channel(EngineRelayerHandler::ENGINE_PACKET_QUEUE_CAPACITY);
+    let packet_delay = Duration::from_millis(args.packet_delay_ms);
+    let forwarder_threads = start_forward_and_delay_thread(
+        verified_receiver,
+        delay_packet_sender,
+        engine_sender,
+        Arc::new(FixedDelay(packet_delay)),
+        Arc::new(RwLock::new(HealthState::Healthy)),
+        ForwarderConfig {
+            num_threads: args.forwarder_threads,
+            disable_mempool: args.disable_mempool,
+            dedup: (args.dedup_ttl_secs > 0).then_some(DedupConfig {
+                ttl_secs: args.dedup_ttl_secs,
+                capacity: 100_000,
+            }),
+            // Long enough to release everything still delayed once the
+            // capture runs out.
+            drain_timeout: packet_delay + Duration::from_secs(1),
+            ..ForwarderConfig::default()
+        },
+        &Arc::new(ForwarderStats::default()),
+        Arc::new(LogMetricsSink),
+        &exit,
+    )
+    .map_err(|e| format!("Failed to start forwarder: {e}"))?;
+
+    let validator_packets = thread::spawn(move || {
+        delay_packet_receiver
+            .iter()
+            .map(|batch| batch.len() as u64)
+            .sum::<u64>()
+    });
+    let engine_packets = thread::spawn(move || {
+        let mut num_packets = 0;
+        while let Some(batch) = engine_receiver.blocking_recv() {
+            num_packets += batch.len() as u64;
+        }
+        num_packets
+    });
+
+    let start = Instant::now();
+    let mut first_timestamp_us = None;
+    let (mut num_batches, mut num_packets) = (0, 0);
+    for batch in capture {
+        let batch = batch.map_err(|e| format!("Failed to read capture: {e}"))?;
+        if args.speed > 0.0 {
+            let first_timestamp_us = *first_timestamp_us.get_or_insert(batch.timestamp_us);
+            let offset_us = batch.timestamp_us.saturating_sub(first_timestamp_us);
+            let due = start + Duration::from_secs_f64(offset_us as f64 / 1e6 / args.speed);
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+        }
+        num_batches += 1;
+        num_packets += batch.packets.len() as u64;
+        verified_sender
+            .send(batch.to_packet_batch())
+            .map_err(|_| "Forwarder stopped before the capture was replayed".to_string())?;
+    }
+    let replay_elapsed = start.elapsed();
+
+    // Workers finish the batches still queued before the release thread
+    // starts draining, so the whole capture is accounted for.
+    drop(verified_sender);
+    exit.store(true, Ordering::Relaxed);
+    for t in forwarder_threads {
+        t.join().unwrap();
+    }
+
+    info!(
+        "Replayed {num_batches} batches ({num_packets} packets) in {replay_elapsed:?}: {} packets released to validators, {} forwarded to the block engine",
+        validator_packets.join().unwrap(),
+        engine_packets.join().unwrap()
+    );
+    Ok(())
+}
@@ -0,0 +1,284 @@
+//! Packet capture files, so production traffic can be reproduced locally by
+//! replaying it through the forwarder with the `replay` binary.
+//!
+//! A capture is [`CAPTURE_MAGIC`] followed by bincode-encoded
+//! [`CapturedBatch`] records until the end of the file.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::SocketAddr,
+    path::Path,
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use blocks::packet::{Packet, PacketBatch};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+pub const CAPTURE_MAGIC: &[u8; 8] = b"RLYCAP01";
+/// Default size at which a capture stops growing.
+pub const DEFAULT_CAPTURE_MAX_BYTES: u64 = 1 << 30;
+/// Batches waiting to be written before new ones are dropped.
+const CAPTURE_QUEUE_CAPACITY: usize = 10_000;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize)]
+pub struct CapturedPacket {
+    pub addr: SocketAddr,
+    pub staked: bool,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CapturedBatch {
+    /// When the batch's first packet arrived, in microseconds since the Unix
+    /// epoch.
+    pub timestamp_us: u64,
+    pub packets: Vec<CapturedPacket>,
+}
+
+impl CapturedBatch {
+    fn new(batch: &PacketBatch) -> Self {
+        let arrival = batch
+            .iter()
+            .next()
+            .map_or_else(Instant::now, |packet| packet.meta.arrival);
+        let timestamp = SystemTime::now() - arrival.elapsed();
+        CapturedBatch {
+            timestamp_us: timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64,
+            packets: batch
+                .iter()
+                .map(|packet| CapturedPacket {
+                    addr: packet.meta.addr,
+                    staked: packet.meta.staked,
+                    data: packet.data().to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Rebuilds the batch, with packets arriving now.
+    pub fn to_packet_batch(&self) -> PacketBatch {
+        self.packets
+            .iter()
+            .map(|packet| Packet::new(packet.data.clone(), packet.addr, packet.staked))
+            .collect()
+    }
+}
+
+/// Hands batches to the capture thread. Capturing never blocks forwarding:
+/// batches are dropped when the writer falls behind.
+#[derive(Clone)]
+pub struct CaptureSender {
+    sender: Sender<PacketBatch>,
+}
+
+impl CaptureSender {
+    /// Queues a copy of `batch`, returning false if it was dropped because
+    /// the capture queue is full.
+    pub fn capture(&self, batch: &PacketBatch) -> bool {
+        match self.sender.try_send(batch.clone()) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => true,
+            Err(TrySendError::Full(_)) => false,
+        }
+    }
+}
+
+/// Creates the capture file at `path` and spawns the thread writing to it.
+/// The thread flushes and exits once every [`CaptureSender`] is dropped, or
+/// once the next batch would take the file past `max_bytes`; 0 means no
+/// limit.
+pub fn start_capture(path: &Path, max_bytes: u64) -> io::Result<(CaptureSender, JoinHandle<()>)> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(CAPTURE_MAGIC)?;
+    let (sender, receiver) = crossbeam_channel::bounded(CAPTURE_QUEUE_CAPACITY);
+    info!("Capturing forwarded packets to {}", path.display());
+
+    let thread = Builder::new()
+        .name("packet_capture".into())
+        .spawn(move || {
+            if let Err(e) = write_captures(&receiver, &mut writer, max_bytes) {
+                error!("Packet capture failed, no longer capturing: {e}");
+            }
+        })?;
+    Ok((CaptureSender { sender }, thread))
+}
+
+fn write_captures(
+    receiver: &Receiver<PacketBatch>,
+    writer: &mut BufWriter<File>,
+    max_bytes: u64,
+) -> Result<(), String> {
+    let mut written = CAPTURE_MAGIC.len() as u64;
+    loop {
+        match receiver.recv_timeout(FLUSH_INTERVAL) {
+            Ok(batch) => {
+                if batch.is_empty() {
+                    continue;
+                }
+                let captured = CapturedBatch::new(&batch);
+                let size = bincode::serialized_size(&captured).map_err(|e| e.to_string())?;
+                if max_bytes > 0 && written + size > max_bytes {
+                    info!("Packet capture reached {written} bytes, no longer capturing");
+                    return writer.flush().map_err(|e| e.to_string());
+                }
+                bincode::serialize_into(&mut *writer, &captured).map_err(|e| e.to_string())?;
+                written += size;
+            }
+            Err(RecvTimeoutError::Timeout) => writer.flush().map_err(|e| e.to_string())?,
+            Err(RecvTimeoutError::Disconnected) => {
+                return writer.flush().map_err(|e| e.to_string());
+            }
+        }
+    }
+}
+
+/// Reads the batches of a capture file in the order they were received.
+///
+/// A record cut short at the end of the file, as left by a relayer killed
+/// mid-write, ends the capture with a warning rather than an error.
+pub struct CaptureReader {
+    reader: BufReader<File>,
+}
+
+impl CaptureReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; CAPTURE_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a packet capture file",
+            ));
+        }
+        Ok(CaptureReader { reader })
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = Result<CapturedBatch, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.fill_buf() {
+            Ok([]) => return None,
+            Ok(_) => {}
+            Err(e) => return Some(Err(e.to_string())),
+        }
+        match bincode::deserialize_from(&mut self.reader) {
+            Ok(batch) => Some(Ok(batch)),
+            Err(e) => match *e {
+                bincode::ErrorKind::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    warn!("Capture ends with a truncated record, ignoring it");
+                    None
+                }
+                e => Some(Err(e.to_string())),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use super::*;
+
+    fn capture_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("relayer-capture-{}-{name}", process::id()))
+    }
+
+    fn batch(tags: &[u8]) -> PacketBatch {
+        tags.iter()
+            .map(|tag| {
+                Packet::new(
+                    vec![*tag; 100],
+                    "10.0.0.1:8001".parse().unwrap(),
+                    *tag % 2 == 0,
+                )
+            })
+            .collect()
+    }
+
+    fn capture(path: &Path, max_bytes: u64, batches: &[PacketBatch]) -> Vec<CapturedBatch> {
+        let (sender, thread) = start_capture(path, max_bytes).unwrap();
+        for batch in batches {
+            assert!(sender.capture(batch));
+        }
+        drop(sender);
+        thread.join().unwrap();
+        let captured = CaptureReader::open(path)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        fs::remove_file(path).unwrap();
+        captured
+    }
+
+    #[test]
+    fn reads_back_what_was_captured() {
+        let path = capture_path("round-trip");
+        let captured = capture(&path, 0, &[batch(&[1, 2]), batch(&[]), batch(&[3])]);
+
+        assert_eq!(captured.len(), 2);
+        let packets: Vec<_> = captured
+            .iter()
+            .flat_map(|batch| batch.to_packet_batch())
+            .collect();
+        assert_eq!(packets.len(), 3);
+        for (packet, tag) in packets.iter().zip([1, 2, 3]) {
+            assert_eq!(packet.data(), &[tag; 100][..]);
+            assert_eq!(packet.meta.addr, "10.0.0.1:8001".parse().unwrap());
+            assert_eq!(packet.meta.staked, tag % 2 == 0);
+        }
+        assert!(captured[0].timestamp_us <= captured[1].timestamp_us);
+    }
+
+    #[test]
+    fn stops_at_the_size_limit() {
+        let path = capture_path("limit");
+        let captured = capture(&path, 300, &[batch(&[1]), batch(&[2]), batch(&[3])]);
+        assert_eq!(captured.len(), 2);
+    }
+
+    #[test]
+    fn ends_at_a_truncated_final_record() {
+        let path = capture_path("truncated");
+        let (sender, thread) = start_capture(&path, 0).unwrap();
+        for tags in [[1], [2]] {
+            assert!(sender.capture(&batch(&tags)));
+        }
+        drop(sender);
+        thread.join().unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 10)
+            .unwrap();
+
+        let captured = CaptureReader::open(&path)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(captured.len(), 1);
+        assert_eq!(captured[0].packets[0].data, vec![1; 100]);
+    }
+
+    #[test]
+    fn open_rejects_other_files() {
+        let path = capture_path("not-a-capture");
+        fs::write(&path, b"not a capture").unwrap();
+        let result = CaptureReader::open(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,158 @@
+use std::{ffi::OsString, fs, path::Path};
+
+use clap::{parser::ValueSource, ArgMatches, Command};
+use serde_yaml::{Mapping, Value};
+
+/// Reads a YAML settings file and turns it into `--flag=value` arguments for
+/// `command`, so file values go through the same parsers as flags.
+///
+/// Keys are flag names in snake_case (`packet_delay_ms: 150`); lists become
+/// one argument per item. Settings given on the command line or through the
+/// environment in `matches` are skipped, so those override the file.
+pub fn file_args(
+    path: &Path,
+    command: &Command,
+    matches: &ArgMatches,
+) -> Result<Vec<OsString>, String> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+    let settings: Mapping = serde_yaml::from_str(&contents)
+        .map_err(|e| format!("failed to parse {}: {e}", path.display()))?;
+
+    let mut args = Vec::new();
+    for (key, value) in settings {
+        let key = key
+            .as_str()
+            .ok_or_else(|| format!("{}: keys must be strings", path.display()))?
+            .replace('-', "_");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == key.as_str() && arg.get_long().is_some())
+            .filter(|arg| arg.get_id() != "config")
+            .ok_or_else(|| format!("{}: unknown setting {key}", path.display()))?;
+        if matches!(
+            matches.value_source(&key),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+
+        let long = arg.get_long().unwrap();
+        if !arg.get_action().takes_values() {
+            match value {
+                Value::Bool(true) => args.push(format!("--{long}").into()),
+                Value::Bool(false) | Value::Null => {}
+                _ => return Err(format!("{}: {key} must be true or false", path.display())),
+            }
+            continue;
+        }
+        let values = match value {
+            Value::Sequence(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::Null => continue,
+                Value::String(value) => value,
+                Value::Bool(value) => value.to_string(),
+                Value::Number(value) => value.to_string(),
+                _ => {
+                    return Err(format!(
+                        "{}: {key} must be a scalar or list",
+                        path.display()
+                    ))
+                }
+            };
+            args.push(format!("--{long}={value}").into());
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::PathBuf, process};
+
+    use clap::{Arg, ArgAction};
+
+    use super::*;
+
+    fn command() -> Command {
+        Command::new("relayer")
+            .arg(Arg::new("config").long("config"))
+            .arg(Arg::new("packet_delay_ms").long("packet-delay-ms"))
+            .arg(
+                Arg::new("dedup_ttl_secs")
+                    .long("dedup-ttl-secs")
+                    .env("CONFIG_TEST_DEDUP_TTL_SECS"),
+            )
+            .arg(
+                Arg::new("program_denylist")
+                    .long("program-denylist")
+                    .action(ArgAction::Append),
+            )
+            .arg(
+                Arg::new("filter_packets")
+                    .long("filter-packets")
+                    .action(ArgAction::SetTrue),
+            )
+    }
+
+    fn write_config(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("relayer-config-{}-{name}.yaml", process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn file_args_for(name: &str, contents: &str, cli: &[&str]) -> Result<Vec<String>, String> {
+        let path = write_config(name, contents);
+        let matches = command().get_matches_from(["relayer"].iter().chain(cli));
+        let args = file_args(&path, &command(), &matches);
+        fs::remove_file(&path).unwrap();
+        args.map(|args| {
+            args.into_iter()
+                .map(|arg| arg.into_string().unwrap())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn converts_settings_to_flags() {
+        let args = file_args_for(
+            "convert",
+            "packet-delay-ms: 150\nprogram_denylist: [a, b]\nfilter_packets: true\n",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "--packet-delay-ms=150",
+                "--program-denylist=a",
+                "--program-denylist=b",
+                "--filter-packets",
+            ]
+        );
+    }
+
+    #[test]
+    fn command_line_and_environment_override_the_file() {
+        env::set_var("CONFIG_TEST_DEDUP_TTL_SECS", "5");
+        let args = file_args_for(
+            "precedence",
+            "packet_delay_ms: 150\ndedup_ttl_secs: 2\nfilter_packets: false\n",
+            &["--packet-delay-ms", "10"],
+        );
+        env::remove_var("CONFIG_TEST_DEDUP_TTL_SECS");
+        assert_eq!(args.unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rejects_unknown_settings_and_the_config_key() {
+        let error = file_args_for("unknown", "packet_delay: 150\n", &[]).unwrap_err();
+        assert!(error.ends_with("unknown setting packet_delay"), "{error}");
+
+        let error = file_args_for("nested", "config: other.yaml\n", &[]).unwrap_err();
+        assert!(error.ends_with("unknown setting config"), "{error}");
+    }
+}
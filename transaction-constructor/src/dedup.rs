@@ -0,0 +1,107 @@
+use std::sync::Mutex;
+
+use blocks::packet::PacketBatch;
+use cached::{Cached, TimedSizedCache};
+use solana_sdk::signature::Signature;
+
+#[derive(Clone, Copy, Debug)]
+pub struct DedupConfig {
+    /// How long a signature is remembered after it was first seen.
+    pub ttl_secs: u64,
+    /// Maximum number of signatures remembered at once.
+    pub capacity: usize,
+}
+
+/// Drops packets whose transaction signature was already seen recently.
+///
+/// Clients send the same transaction to every TPU, so without this each copy
+/// would be forwarded to the block engine and validators.
+pub struct Deduper {
+    seen: Mutex<TimedSizedCache<Signature, ()>>,
+}
+
+impl Deduper {
+    pub fn new(config: DedupConfig) -> Self {
+        Deduper {
+            seen: Mutex::new(TimedSizedCache::with_size_and_lifespan(
+                config.capacity,
+                config.ttl_secs,
+            )),
+        }
+    }
+
+    /// Returns the batch without duplicates, and the number removed.
+    /// Packets without a parseable signature are kept.
+    pub fn dedup(&self, batch: PacketBatch) -> (PacketBatch, u64) {
+        let mut seen = self.seen.lock().unwrap();
+        let mut duplicates = 0;
+        let batch = batch
+            .into_iter()
+            .filter(|packet| match packet.meta.signature {
+                Some(signature) => {
+                    let duplicate = seen.cache_get(&signature).is_some();
+                    if duplicate {
+                        duplicates += 1;
+                    } else {
+                        seen.cache_set(signature, ());
+                    }
+                    !duplicate
+                }
+                None => true,
+            })
+            .collect();
+        (batch, duplicates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blocks::packet::Packet;
+
+    use super::*;
+
+    /// A packet whose first signature is 64 copies of `tag`.
+    fn packet(tag: u8) -> Packet {
+        let mut data = vec![1];
+        data.extend_from_slice(&[tag; 64]);
+        Packet::new(data, "127.0.0.1:0".parse().unwrap(), false)
+    }
+
+    fn deduper(capacity: usize) -> Deduper {
+        Deduper::new(DedupConfig {
+            ttl_secs: 60,
+            capacity,
+        })
+    }
+
+    #[test]
+    fn drops_repeated_signatures_within_and_across_batches() {
+        let deduper = deduper(100);
+        let (batch, duplicates) =
+            deduper.dedup(PacketBatch::new(vec![packet(1), packet(2), packet(1)]));
+        assert_eq!(batch.len(), 2);
+        assert_eq!(duplicates, 1);
+
+        let (batch, duplicates) = deduper.dedup(PacketBatch::new(vec![packet(2), packet(3)]));
+        assert_eq!(batch.iter().next().unwrap().data()[1], 3);
+        assert_eq!(duplicates, 1);
+    }
+
+    #[test]
+    fn keeps_packets_without_a_signature() {
+        let deduper = deduper(100);
+        let unsigned = || Packet::new(vec![0], "127.0.0.1:0".parse().unwrap(), false);
+        let (batch, duplicates) = deduper.dedup(PacketBatch::new(vec![unsigned(), unsigned()]));
+        assert_eq!(batch.len(), 2);
+        assert_eq!(duplicates, 0);
+    }
+
+    #[test]
+    fn forgets_signatures_beyond_capacity() {
+        let deduper = deduper(1);
+        deduper.dedup(PacketBatch::new(vec![packet(1), packet(2)]));
+        let (batch, duplicates) = deduper.dedup(PacketBatch::new(vec![packet(1)]));
+        assert_eq!(batch.len(), 1);
+        assert_eq!(duplicates, 0);
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use blocks::packet::{first_signer, Packet};
+use builder_block::{relayer::LeaderScheduleUpdatingHandle, rpc::LoadBalancer};
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+
+/// Decides how long each packet is held before it is sent to validators.
+pub trait DelayPolicy: Send + Sync {
+    fn delay(&self, packet: &Packet) -> Duration;
+}
+
+/// Holds every packet for the same duration.
+pub struct FixedDelay(pub Duration);
+
+impl DelayPolicy for FixedDelay {
+    fn delay(&self, _packet: &Packet) -> Duration {
+        self.0
+    }
+}
+
+/// Sends packets signed by allowlisted pubkeys immediately and defers to
+/// `inner` for everything else.
+pub struct AllowlistDelay {
+    allowlist: HashSet<Pubkey>,
+    inner: Arc<dyn DelayPolicy>,
+}
+
+impl AllowlistDelay {
+    pub fn new(allowlist: HashSet<Pubkey>, inner: Arc<dyn DelayPolicy>) -> Self {
+        AllowlistDelay { allowlist, inner }
+    }
+}
+
+impl DelayPolicy for AllowlistDelay {
+    fn delay(&self, packet: &Packet) -> Duration {
+        match first_signer(packet.data()) {
+            Some(signer) if self.allowlist.contains(&signer) => Duration::ZERO,
+            _ => self.inner.delay(packet),
+        }
+    }
+}
+
+/// How many slots remain until a validator we relay for is leader.
+pub trait LeaderProximity: Send + Sync {
+    fn slots_until_leader(&self) -> Option<Slot>;
+}
+
+/// How long a computed proximity is reused before the schedule is queried
+/// again; well under one slot.
+const PROXIMITY_REFRESH_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Looks up upcoming leaders in the relayer's leader schedule cache.
+pub struct ScheduleProximity {
+    leader_cache: LeaderScheduleUpdatingHandle,
+    rpc_load_balancer: Arc<LoadBalancer>,
+    validators: HashSet<Pubkey>,
+    lookahead_slots: Slot,
+    last: Mutex<Option<(Instant, Option<Slot>)>>,
+}
+
+impl ScheduleProximity {
+    pub fn new(
+        leader_cache: LeaderScheduleUpdatingHandle,
+        rpc_load_balancer: Arc<LoadBalancer>,
+        validators: HashSet<Pubkey>,
+        lookahead_slots: Slot,
+    ) -> Self {
+        ScheduleProximity {
+            leader_cache,
+            rpc_load_balancer,
+            validators,
+            lookahead_slots,
+            last: Mutex::new(None),
+        }
+    }
+}
+
+impl LeaderProximity for ScheduleProximity {
+    fn slots_until_leader(&self) -> Option<Slot> {
+        let mut last = self.last.lock().unwrap();
+        if let Some((computed_at, slots)) = *last {
+            if computed_at.elapsed() < PROXIMITY_REFRESH_INTERVAL {
+                return slots;
+            }
+        }
+
+        let current_slot = self.rpc_load_balancer.get_highest_slot();
+        let slots = (0..self.lookahead_slots).find(|offset| {
+            self.leader_cache
+                .leaders_for_slots(&[current_slot + offset])
+                .iter()
+                .any(|leader| self.validators.contains(leader))
+        });
+        *last = Some((Instant::now(), slots));
+        slots
+    }
+}
+
+/// Scales the delay linearly from `near_delay`, when a relayed validator is
+/// leader now, to `far_delay`, when none leads within `lookahead_slots`.
+pub struct LeaderProximityDelay {
+    proximity: Arc<dyn LeaderProximity>,
+    near_delay: Duration,
+    far_delay: Duration,
+    lookahead_slots: Slot,
+}
+
+impl LeaderProximityDelay {
+    pub fn new(
+        proximity: Arc<dyn LeaderProximity>,
+        near_delay: Duration,
+        far_delay: Duration,
+        lookahead_slots: Slot,
+    ) -> Self {
+        LeaderProximityDelay {
+            proximity,
+            near_delay,
+            far_delay,
+            lookahead_slots: lookahead_slots.max(1),
+        }
+    }
+}
+
+impl DelayPolicy for LeaderProximityDelay {
+    fn delay(&self, _packet: &Packet) -> Duration {
+        let Some(slots) = self.proximity.slots_until_leader() else {
+            return self.far_delay;
+        };
+        let fraction = slots.min(self.lookahead_slots) as f64 / self.lookahead_slots as f64;
+        if self.far_delay >= self.near_delay {
+            self.near_delay + (self.far_delay - self.near_delay).mul_f64(fraction)
+        } else {
+            self.near_delay - (self.near_delay - self.far_delay).mul_f64(fraction)
+        }
+    }
+}
+
+/// Delegates to a policy that can be replaced while the forwarder runs.
+pub struct SwitchableDelayPolicy {
+    current: RwLock<Arc<dyn DelayPolicy>>,
+}
+
+impl SwitchableDelayPolicy {
+    pub fn new(policy: Arc<dyn DelayPolicy>) -> Self {
+        SwitchableDelayPolicy {
+            current: RwLock::new(policy),
+        }
+    }
+
+    pub fn set(&self, policy: Arc<dyn DelayPolicy>) {
+        *self.current.write().unwrap() = policy;
+    }
+}
+
+impl DelayPolicy for SwitchableDelayPolicy {
+    fn delay(&self, packet: &Packet) -> Duration {
+        self.current.read().unwrap().delay(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{
+        hash::Hash,
+        signature::{Keypair, Signer},
+        system_transaction,
+    };
+
+    use super::*;
+
+    fn signed_by(keypair: &Keypair) -> Packet {
+        let transaction =
+            system_transaction::transfer(keypair, &Pubkey::new_unique(), 1, Hash::default());
+        Packet::new(
+            bincode::serialize(&transaction).unwrap(),
+            "127.0.0.1:0".parse().unwrap(),
+            false,
+        )
+    }
+
+    struct FixedProximity(Option<Slot>);
+
+    impl LeaderProximity for FixedProximity {
+        fn slots_until_leader(&self) -> Option<Slot> {
+            self.0
+        }
+    }
+
+    fn proximity_delay(slots: Option<Slot>) -> Duration {
+        LeaderProximityDelay::new(
+            Arc::new(FixedProximity(slots)),
+            Duration::from_millis(0),
+            Duration::from_millis(200),
+            10,
+        )
+        .delay(&signed_by(&Keypair::new()))
+    }
+
+    #[test]
+    fn allowlisted_signers_are_not_delayed() {
+        let allowlisted = Keypair::new();
+        let policy = AllowlistDelay::new(
+            HashSet::from([allowlisted.pubkey()]),
+            Arc::new(FixedDelay(Duration::from_millis(200))),
+        );
+        assert_eq!(policy.delay(&signed_by(&allowlisted)), Duration::ZERO);
+        assert_eq!(
+            policy.delay(&signed_by(&Keypair::new())),
+            Duration::from_millis(200)
+        );
+    }
+
+    #[test]
+    fn leader_proximity_scales_between_near_and_far_delays() {
+        assert_eq!(proximity_delay(Some(0)), Duration::ZERO);
+        assert_eq!(proximity_delay(Some(5)), Duration::from_millis(100));
+        assert_eq!(proximity_delay(Some(50)), Duration::from_millis(200));
+        assert_eq!(proximity_delay(None), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn switchable_policy_uses_the_latest_policy() {
+        let policy = SwitchableDelayPolicy::new(Arc::new(FixedDelay(Duration::from_millis(200))));
+        let packet = signed_by(&Keypair::new());
+        assert_eq!(policy.delay(&packet), Duration::from_millis(200));
+        policy.set(Arc::new(FixedDelay(Duration::from_millis(50))));
+        assert_eq!(policy.delay(&packet), Duration::from_millis(50));
+    }
+}
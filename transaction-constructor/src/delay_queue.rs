@@ -0,0 +1,283 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use blocks::packet::Packet;
+
+/// Longest delay a packet is held for; longer delays are clamped so the
+/// release time can't overflow.
+pub const MAX_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What a full [`DelayQueue`] does with a new packet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DropPolicy {
+    /// Evict the earliest-arrived packet to make room, whatever its delay.
+    #[default]
+    DropOldest,
+    /// Reject the new packet.
+    DropNewest,
+    /// Block the pushing thread until there is room.
+    Backpressure,
+}
+
+pub enum PushOutcome {
+    Queued,
+    /// The queue was full and a packet was discarded under the drop policy.
+    Dropped,
+    /// The queue stayed full for the whole wait under backpressure; the
+    /// packet is handed back so the caller can retry.
+    Full(Packet),
+}
+
+/// Bounded queue shared by all forwarder threads, ordered by release time.
+///
+/// Each packet is stamped under the queue lock and keyed by its release
+/// time plus an arrival sequence number, so packets with equal delays leave
+/// in arrival order across every thread and the first entry is always the
+/// next packet due.
+pub struct DelayQueue {
+    packets: Mutex<QueueState>,
+    available: Condvar,
+    space: Condvar,
+    capacity: usize,
+    drop_policy: DropPolicy,
+}
+
+#[derive(Default)]
+struct QueueState {
+    next_seq: u64,
+    /// (release time, sequence) -> packet
+    entries: BTreeMap<(Instant, u64), Packet>,
+    /// sequence -> release time, the same packets in arrival order
+    arrivals: BTreeMap<u64, Instant>,
+}
+
+impl DelayQueue {
+    pub fn new(capacity: usize, drop_policy: DropPolicy) -> Self {
+        assert!(capacity > 0, "DelayQueue capacity must be positive");
+        DelayQueue {
+            packets: Mutex::new(QueueState::default()),
+            available: Condvar::new(),
+            space: Condvar::new(),
+            capacity,
+            drop_policy,
+        }
+    }
+
+    /// Queues `packet` for release after `delay`, at most [`MAX_DELAY`],
+    /// applying the drop policy if the queue is full. Under backpressure this waits up to `timeout`
+    /// for room.
+    pub fn push(&self, packet: Packet, delay: Duration, timeout: Duration) -> PushOutcome {
+        let mut state = self.packets.lock().unwrap();
+        let mut outcome = PushOutcome::Queued;
+        if state.entries.len() >= self.capacity {
+            match self.drop_policy {
+                DropPolicy::DropOldest => {
+                    let (seq, release) = state.arrivals.pop_first().unwrap();
+                    state.entries.remove(&(release, seq));
+                    outcome = PushOutcome::Dropped;
+                }
+                DropPolicy::DropNewest => return PushOutcome::Dropped,
+                DropPolicy::Backpressure => {
+                    state = self
+                        .space
+                        .wait_timeout_while(state, timeout, |s| s.entries.len() >= self.capacity)
+                        .unwrap()
+                        .0;
+                    if state.entries.len() >= self.capacity {
+                        return PushOutcome::Full(packet);
+                    }
+                }
+            }
+        }
+        let now = Instant::now();
+        let latest = now + MAX_DELAY;
+        let release = now.checked_add(delay).map_or(latest, |t| t.min(latest));
+        let key = (release, state.next_seq);
+        state.next_seq += 1;
+        state.entries.insert(key, packet);
+        state.arrivals.insert(key.1, key.0);
+        if state.entries.first_key_value().map(|(k, _)| *k) == Some(key) {
+            self.available.notify_one();
+        }
+        outcome
+    }
+
+    /// Waits up to `timeout` for the first packet to reach its release time,
+    /// then returns it and every other due packet, up to `max_packets`, with
+    /// the time each was due. Returns an empty batch if nothing became due in
+    /// time.
+    pub fn pop_due(&self, timeout: Duration, max_packets: usize) -> Vec<(Instant, Packet)> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.packets.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let wait = match state.entries.first_key_value() {
+                Some(((release, _), _)) if *release <= now => break,
+                Some(((release, _), _)) => (*release).min(deadline),
+                None => deadline,
+            };
+            if wait <= now {
+                return Vec::new();
+            }
+            state = self.available.wait_timeout(state, wait - now).unwrap().0;
+        }
+
+        let now = Instant::now();
+        let mut due = Vec::new();
+        while due.len() < max_packets {
+            match state.entries.first_entry() {
+                Some(entry) if entry.key().0 <= now => {
+                    let ((release, seq), packet) = entry.remove_entry();
+                    state.arrivals.remove(&seq);
+                    due.push((release, packet));
+                }
+                _ => break,
+            }
+        }
+        self.space.notify_all();
+        due
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(tag: u8) -> Packet {
+        Packet::new(vec![tag], "127.0.0.1:0".parse().unwrap(), false)
+    }
+
+    fn tags(due: &[(Instant, Packet)]) -> Vec<u8> {
+        due.iter().map(|(_, packet)| packet.data()[0]).collect()
+    }
+
+    #[test]
+    fn equal_delays_release_in_arrival_order() {
+        let queue = DelayQueue::new(10, DropPolicy::DropNewest);
+        for tag in 0..5 {
+            queue.push(packet(tag), Duration::ZERO, Duration::ZERO);
+        }
+        let due = queue.pop_due(Duration::from_millis(10), 10);
+        assert_eq!(tags(&due), vec![0, 1, 2, 3, 4]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn shorter_delays_release_first() {
+        let queue = DelayQueue::new(10, DropPolicy::DropNewest);
+        queue.push(packet(0), Duration::from_millis(30), Duration::ZERO);
+        queue.push(packet(1), Duration::ZERO, Duration::ZERO);
+
+        assert_eq!(tags(&queue.pop_due(Duration::from_millis(10), 10)), vec![1]);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(tags(&queue.pop_due(Duration::from_secs(1), 10)), vec![0]);
+    }
+
+    #[test]
+    fn pop_due_reports_when_each_packet_was_due() {
+        let queue = DelayQueue::new(10, DropPolicy::DropNewest);
+        let pushed = Instant::now();
+        queue.push(packet(0), Duration::from_millis(20), Duration::ZERO);
+
+        let due = queue.pop_due(Duration::from_secs(1), 10);
+        let (due_at, _) = &due[0];
+        assert!(*due_at >= pushed + Duration::from_millis(20));
+        assert!(*due_at <= Instant::now());
+    }
+
+    #[test]
+    fn pop_due_waits_at_most_the_timeout() {
+        let queue = DelayQueue::new(10, DropPolicy::DropNewest);
+        queue.push(packet(0), Duration::from_secs(60), Duration::ZERO);
+
+        let start = Instant::now();
+        assert!(queue.pop_due(Duration::from_millis(20), 10).is_empty());
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn drop_newest_rejects_packets_when_full() {
+        let queue = DelayQueue::new(2, DropPolicy::DropNewest);
+        for tag in 0..2 {
+            assert!(matches!(
+                queue.push(packet(tag), Duration::ZERO, Duration::ZERO),
+                PushOutcome::Queued
+            ));
+        }
+        assert!(matches!(
+            queue.push(packet(2), Duration::ZERO, Duration::ZERO),
+            PushOutcome::Dropped
+        ));
+        assert_eq!(tags(&queue.pop_due(Duration::ZERO, 10)), vec![0, 1]);
+    }
+
+    #[test]
+    fn drop_oldest_evicts_by_arrival_not_release_time() {
+        let queue = DelayQueue::new(2, DropPolicy::DropOldest);
+        queue.push(packet(0), Duration::from_millis(50), Duration::ZERO);
+        queue.push(packet(1), Duration::ZERO, Duration::ZERO);
+        assert!(matches!(
+            queue.push(packet(2), Duration::ZERO, Duration::ZERO),
+            PushOutcome::Dropped
+        ));
+
+        // Packet 0 arrived first, so it is evicted even though the
+        // zero-delay packet 1 was due sooner.
+        assert_eq!(tags(&queue.pop_due(Duration::ZERO, 10)), vec![1, 2]);
+        assert!(queue.is_empty());
+        assert!(queue.packets.lock().unwrap().arrivals.is_empty());
+    }
+
+    #[test]
+    fn backpressure_hands_the_packet_back_until_there_is_room() {
+        let queue = DelayQueue::new(1, DropPolicy::Backpressure);
+        queue.push(packet(0), Duration::ZERO, Duration::ZERO);
+
+        let returned = match queue.push(packet(1), Duration::ZERO, Duration::from_millis(10)) {
+            PushOutcome::Full(packet) => packet,
+            _ => panic!("expected the full queue to hand the packet back"),
+        };
+        assert_eq!(tags(&queue.pop_due(Duration::ZERO, 10)), vec![0]);
+        assert!(matches!(
+            queue.push(returned, Duration::ZERO, Duration::ZERO),
+            PushOutcome::Queued
+        ));
+        assert_eq!(tags(&queue.pop_due(Duration::ZERO, 10)), vec![1]);
+    }
+
+    #[test]
+    fn huge_delays_are_clamped() {
+        let queue = DelayQueue::new(10, DropPolicy::DropNewest);
+        let before = Instant::now();
+        assert!(matches!(
+            queue.push(packet(0), Duration::MAX, Duration::ZERO),
+            PushOutcome::Queued
+        ));
+        let release = queue.packets.lock().unwrap().arrivals[&0];
+        assert!(release <= Instant::now() + MAX_DELAY);
+        assert!(release >= before + MAX_DELAY);
+        assert!(queue.pop_due(Duration::ZERO, 10).is_empty());
+    }
+
+    #[test]
+    fn pop_due_returns_at_most_max_packets() {
+        let queue = DelayQueue::new(10, DropPolicy::DropNewest);
+        for tag in 0..5 {
+            queue.push(packet(tag), Duration::ZERO, Duration::ZERO);
+        }
+        assert_eq!(tags(&queue.pop_due(Duration::ZERO, 2)), vec![0, 1]);
+        assert_eq!(queue.len(), 3);
+    }
+}
@@ -0,0 +1,579 @@
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
+};
+
+use blocks::{metrics::MetricsSink, packet::PacketBatch, supervisor::spawn_supervised};
+use builder_block::{
+    proto::{
+        relayer::{
+            relayer_server::Relayer, subscribe_packets_response::Msg, GetTpuConfigsRequest,
+            GetTpuConfigsResponse, SubscribePacketsRequest, SubscribePacketsResponse,
+        },
+        shared::{Header, Heartbeat, Socket},
+    },
+    relayer::LeaderScheduleUpdatingHandle,
+    rpc::LoadBalancer,
+};
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use dashmap::DashMap;
+use log::{debug, info, warn};
+use prost_types::Timestamp;
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    time::interval,
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{Request, Response, Status};
+
+pub const SUBSCRIBER_QUEUE_CAPACITY: usize = 1_000;
+pub const LEADER_LOOKAHEAD_SLOTS: u64 = 2;
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+/// How long computed leader targets are reused; well under one slot.
+const LEADER_REFRESH_INTERVAL: Duration = Duration::from_millis(50);
+const RECV_TIMEOUT: Duration = Duration::from_millis(5);
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
+type SubscriberSender = mpsc::Sender<Result<SubscribePacketsResponse, Status>>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FanoutConfig {
+    /// Batches buffered per validator before new ones are dropped; must be
+    /// positive.
+    pub subscriber_queue_capacity: usize,
+    /// Packets go only to connected validators leading within this many
+    /// slots, or to every validator if none is. 0 sends every packet to
+    /// every validator.
+    pub leader_lookahead_slots: u64,
+}
+
+impl Default for FanoutConfig {
+    fn default() -> Self {
+        FanoutConfig {
+            subscriber_queue_capacity: SUBSCRIBER_QUEUE_CAPACITY,
+            leader_lookahead_slots: LEADER_LOOKAHEAD_SLOTS,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SubscriberStats {
+    pub packets_sent: AtomicU64,
+    /// Packets dropped because the validator's queue was full.
+    pub packets_dropped: AtomicU64,
+}
+
+struct Subscriber {
+    sender: SubscriberSender,
+    connected_at: Instant,
+    stats: Arc<SubscriberStats>,
+}
+
+/// Per-validator stats for one reporting interval, summed over every
+/// subscriber.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SubscriberTotals {
+    packets_sent: u64,
+    packets_dropped: u64,
+    /// Validators that had at least one packet dropped.
+    validators_dropping: u64,
+    queued_batches: u64,
+    max_queued_batches: u64,
+}
+
+#[derive(Default)]
+struct FanoutStats {
+    packets_received: AtomicU64,
+    batches_to_leaders: AtomicU64,
+    batches_to_all: AtomicU64,
+}
+
+/// Delivers delayed packets to connected validators.
+///
+/// Each validator has its own bounded queue, so a slow one only loses its
+/// own packets: a batch that doesn't fit is dropped for that validator and
+/// counted in its [`SubscriberStats`].
+///
+/// When the fanout thread exits every subscription is dropped, which ends
+/// the validators' streams.
+pub struct PacketFanout {
+    subscribers: DashMap<Pubkey, Subscriber>,
+    config: FanoutConfig,
+    leader_cache: LeaderScheduleUpdatingHandle,
+    rpc_load_balancer: Arc<LoadBalancer>,
+    leader_targets: Mutex<Option<(Instant, HashSet<Pubkey>)>>,
+    stats: FanoutStats,
+}
+
+impl PacketFanout {
+    pub fn new(
+        config: FanoutConfig,
+        leader_cache: LeaderScheduleUpdatingHandle,
+        rpc_load_balancer: Arc<LoadBalancer>,
+    ) -> Self {
+        PacketFanout {
+            subscribers: DashMap::new(),
+            config,
+            leader_cache,
+            rpc_load_balancer,
+            leader_targets: Mutex::new(None),
+            stats: FanoutStats::default(),
+        }
+    }
+
+    pub fn num_subscribers(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    /// Registers `pubkey`, replacing any earlier subscription it had, and
+    /// returns the stream of its packets with a sender into it.
+    fn subscribe(
+        &self,
+        pubkey: Pubkey,
+    ) -> (
+        ReceiverStream<Result<SubscribePacketsResponse, Status>>,
+        SubscriberSender,
+    ) {
+        let (sender, receiver) = mpsc::channel(self.config.subscriber_queue_capacity);
+        let subscriber = Subscriber {
+            sender: sender.clone(),
+            connected_at: Instant::now(),
+            stats: Arc::default(),
+        };
+        if self.subscribers.insert(pubkey, subscriber).is_some() {
+            info!("Validator {pubkey} resubscribed, replacing previous subscription");
+        } else {
+            info!("Validator {pubkey} subscribed");
+        }
+        (ReceiverStream::new(receiver), sender)
+    }
+
+    /// Spawns the thread that routes delayed packets to subscribers until
+    /// the delay stage disconnects or `exit` is set, then drops every
+    /// subscription.
+    pub fn start(
+        self: &Arc<Self>,
+        delay_packet_receiver: Receiver<PacketBatch>,
+        metrics_sink: Arc<dyn MetricsSink>,
+        exit: &Arc<AtomicBool>,
+    ) -> JoinHandle<()> {
+        let fanout = self.clone();
+        let exit = exit.clone();
+        spawn_supervised("packet_fanout_thread".into(), exit.clone(), move || {
+            let mut last_report = Instant::now();
+            loop {
+                if last_report.elapsed() >= METRICS_INTERVAL {
+                    fanout.report(metrics_sink.as_ref());
+                    last_report = Instant::now();
+                }
+                match delay_packet_receiver.recv_timeout(RECV_TIMEOUT) {
+                    Ok(batch) => fanout.route(&batch),
+                    Err(RecvTimeoutError::Timeout) => {
+                        if exit.load(Ordering::Relaxed) {
+                            break;
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            info!(
+                "Packet fanout stopping, closing {} validator streams",
+                fanout.num_subscribers()
+            );
+            fanout.subscribers.clear();
+        })
+    }
+
+    fn route(&self, batch: &PacketBatch) {
+        let num_packets = batch.len() as u64;
+        self.stats
+            .packets_received
+            .fetch_add(num_packets, Ordering::Relaxed);
+        let response = SubscribePacketsResponse {
+            header: Some(Header {
+                ts: Some(Timestamp::from(SystemTime::now())),
+            }),
+            msg: Some(Msg::Batch(batch.to_proto())),
+        };
+
+        let targets = self.leader_targets();
+        if targets.is_some() {
+            self.stats
+                .batches_to_leaders
+                .fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.stats.batches_to_all.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let disconnected =
+            send_to_subscribers(&self.subscribers, targets.as_ref(), &response, num_packets);
+        for pubkey in disconnected {
+            self.remove_if_closed(&pubkey);
+        }
+    }
+
+    /// Connected validators leading within the lookahead window, or `None`
+    /// to send to everyone.
+    fn leader_targets(&self) -> Option<HashSet<Pubkey>> {
+        if self.config.leader_lookahead_slots == 0 {
+            return None;
+        }
+        let mut cached = self.leader_targets.lock().unwrap();
+        let leaders = match &*cached {
+            Some((computed_at, leaders)) if computed_at.elapsed() < LEADER_REFRESH_INTERVAL => {
+                leaders.clone()
+            }
+            _ => {
+                let current_slot = self.rpc_load_balancer.get_highest_slot();
+                let slots: Vec<Slot> =
+                    (current_slot..current_slot + self.config.leader_lookahead_slots).collect();
+                let leaders: HashSet<Pubkey> = self
+                    .leader_cache
+                    .leaders_for_slots(&slots)
+                    .into_iter()
+                    .collect();
+                *cached = Some((Instant::now(), leaders.clone()));
+                leaders
+            }
+        };
+        connected_leaders(leaders, &self.subscribers)
+    }
+
+    fn remove_if_closed(&self, pubkey: &Pubkey) {
+        if let Some((pubkey, subscriber)) = self
+            .subscribers
+            .remove_if(pubkey, |_, subscriber| subscriber.sender.is_closed())
+        {
+            info!(
+                "Validator {pubkey} unsubscribed after {:?}",
+                subscriber.connected_at.elapsed()
+            );
+        }
+    }
+
+    fn report(&self, sink: &dyn MetricsSink) {
+        let totals = take_subscriber_totals(&self.subscribers);
+        sink.submit(
+            "fanout_stats",
+            &[
+                (
+                    "packets_received",
+                    self.stats.packets_received.swap(0, Ordering::Relaxed),
+                ),
+                ("packets_sent", totals.packets_sent),
+                ("packets_dropped", totals.packets_dropped),
+                ("validators_dropping", totals.validators_dropping),
+                (
+                    "batches_to_leaders",
+                    self.stats.batches_to_leaders.swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "batches_to_all",
+                    self.stats.batches_to_all.swap(0, Ordering::Relaxed),
+                ),
+            ],
+        );
+        sink.submit_gauges(
+            "fanout_stats",
+            &[
+                ("connected_validators", self.num_subscribers() as u64),
+                ("queued_batches", totals.queued_batches),
+                ("max_queued_batches", totals.max_queued_batches),
+            ],
+        );
+    }
+}
+
+/// Resets every subscriber's stats for the interval, logging each
+/// validator's, and returns their totals.
+fn take_subscriber_totals(subscribers: &DashMap<Pubkey, Subscriber>) -> SubscriberTotals {
+    let mut totals = SubscriberTotals::default();
+    for subscriber in subscribers.iter() {
+        let sent = subscriber.stats.packets_sent.swap(0, Ordering::Relaxed);
+        let dropped = subscriber.stats.packets_dropped.swap(0, Ordering::Relaxed);
+        let queued = (subscriber.sender.max_capacity() - subscriber.sender.capacity()) as u64;
+        if dropped > 0 {
+            warn!(
+                "Validator {}: sent={sent}, dropped={dropped}, queued_batches={queued}",
+                subscriber.key()
+            );
+            totals.validators_dropping += 1;
+        } else {
+            debug!(
+                "Validator {}: sent={sent}, queued_batches={queued}",
+                subscriber.key()
+            );
+        }
+        totals.packets_sent += sent;
+        totals.packets_dropped += dropped;
+        totals.queued_batches += queued;
+        totals.max_queued_batches = totals.max_queued_batches.max(queued);
+    }
+    totals
+}
+
+/// The leaders among `leaders` that are subscribed, or `None` if none is.
+fn connected_leaders(
+    leaders: HashSet<Pubkey>,
+    subscribers: &DashMap<Pubkey, Subscriber>,
+) -> Option<HashSet<Pubkey>> {
+    let connected: HashSet<Pubkey> = leaders
+        .into_iter()
+        .filter(|leader| subscribers.contains_key(leader))
+        .collect();
+    (!connected.is_empty()).then_some(connected)
+}
+
+/// Queues `response` for every subscriber in `targets`, or for all of them
+/// when `targets` is `None`, and returns those whose stream has closed.
+fn send_to_subscribers(
+    subscribers: &DashMap<Pubkey, Subscriber>,
+    targets: Option<&HashSet<Pubkey>>,
+    response: &SubscribePacketsResponse,
+    num_packets: u64,
+) -> Vec<Pubkey> {
+    let mut disconnected = Vec::new();
+    for subscriber in subscribers.iter() {
+        if targets.is_some_and(|targets| !targets.contains(subscriber.key())) {
+            continue;
+        }
+        match subscriber.sender.try_send(Ok(response.clone())) {
+            Ok(()) => {
+                subscriber
+                    .stats
+                    .packets_sent
+                    .fetch_add(num_packets, Ordering::Relaxed);
+            }
+            Err(TrySendError::Full(_)) => {
+                subscriber
+                    .stats
+                    .packets_dropped
+                    .fetch_add(num_packets, Ordering::Relaxed);
+            }
+            Err(TrySendError::Closed(_)) => disconnected.push(*subscriber.key()),
+        }
+    }
+    disconnected
+}
+
+/// The relayer gRPC service validators subscribe to for packets.
+pub struct FanoutRelayer {
+    fanout: Arc<PacketFanout>,
+    public_ip: IpAddr,
+    tpu_quic_port: u16,
+    exit: Arc<AtomicBool>,
+}
+
+impl FanoutRelayer {
+    pub fn new(
+        fanout: Arc<PacketFanout>,
+        public_ip: IpAddr,
+        tpu_quic_port: u16,
+        exit: &Arc<AtomicBool>,
+    ) -> Self {
+        FanoutRelayer {
+            fanout,
+            public_ip,
+            tpu_quic_port,
+            exit: exit.clone(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Relayer for FanoutRelayer {
+    type SubscribePacketsStream =
+        Pin<Box<dyn Stream<Item = Result<SubscribePacketsResponse, Status>> + Send>>;
+
+    async fn get_tpu_configs(
+        &self,
+        _request: Request<GetTpuConfigsRequest>,
+    ) -> Result<Response<GetTpuConfigsResponse>, Status> {
+        let tpu = Socket {
+            ip: self.public_ip.to_string(),
+            port: self.tpu_quic_port as i64,
+        };
+        Ok(Response::new(GetTpuConfigsResponse {
+            tpu: Some(tpu.clone()),
+            tpu_forward: Some(tpu),
+        }))
+    }
+
+    async fn subscribe_packets(
+        &self,
+        request: Request<SubscribePacketsRequest>,
+    ) -> Result<Response<Self::SubscribePacketsStream>, Status> {
+        // Set by the auth interceptor from the validator's access token.
+        let pubkey = *request
+            .extensions()
+            .get::<Pubkey>()
+            .ok_or_else(|| Status::unauthenticated("Validator identity missing"))?;
+        if self.exit.load(Ordering::Relaxed) {
+            return Err(Status::unavailable("Relayer is shutting down"));
+        }
+        let (stream, sender) = self.fanout.subscribe(pubkey);
+        tokio::spawn(send_heartbeats(
+            self.fanout.clone(),
+            pubkey,
+            sender,
+            self.exit.clone(),
+        ));
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Keeps an otherwise idle subscription alive and unsubscribes the validator
+/// once it disconnects. Heartbeats are skipped while the queue is full,
+/// since packets are flowing. Stops once `exit` is set, leaving the fanout
+/// thread to drop the subscription after its last packets.
+async fn send_heartbeats(
+    fanout: Arc<PacketFanout>,
+    pubkey: Pubkey,
+    sender: SubscriberSender,
+    exit: Arc<AtomicBool>,
+) {
+    let mut heartbeat_interval = interval(HEARTBEAT_INTERVAL);
+    let mut count = 0;
+    loop {
+        heartbeat_interval.tick().await;
+        if exit.load(Ordering::Relaxed) {
+            return;
+        }
+        count += 1;
+        let heartbeat = SubscribePacketsResponse {
+            header: Some(Header {
+                ts: Some(Timestamp::from(SystemTime::now())),
+            }),
+            msg: Some(Msg::Heartbeat(Heartbeat { count })),
+        };
+        if let Err(TrySendError::Closed(_)) = sender.try_send(Ok(heartbeat)) {
+            fanout.remove_if_closed(&pubkey);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blocks::packet::Packet;
+
+    use super::*;
+
+    type SubscriberReceiver = mpsc::Receiver<Result<SubscribePacketsResponse, Status>>;
+
+    fn add_subscriber(
+        subscribers: &DashMap<Pubkey, Subscriber>,
+        capacity: usize,
+    ) -> (Pubkey, SubscriberReceiver) {
+        let pubkey = Pubkey::new_unique();
+        let (sender, receiver) = mpsc::channel(capacity);
+        subscribers.insert(
+            pubkey,
+            Subscriber {
+                sender,
+                connected_at: Instant::now(),
+                stats: Arc::default(),
+            },
+        );
+        (pubkey, receiver)
+    }
+
+    fn response() -> SubscribePacketsResponse {
+        let batch = PacketBatch::new(vec![Packet::new(
+            vec![0],
+            "127.0.0.1:0".parse().unwrap(),
+            false,
+        )]);
+        SubscribePacketsResponse {
+            header: None,
+            msg: Some(Msg::Batch(batch.to_proto())),
+        }
+    }
+
+    #[test]
+    fn sends_to_every_subscriber_without_targets() {
+        let subscribers = DashMap::new();
+        let (_, mut first) = add_subscriber(&subscribers, 1);
+        let (_, mut second) = add_subscriber(&subscribers, 1);
+
+        assert!(send_to_subscribers(&subscribers, None, &response(), 1).is_empty());
+        assert!(first.try_recv().is_ok());
+        assert!(second.try_recv().is_ok());
+    }
+
+    #[test]
+    fn sends_only_to_targets() {
+        let subscribers = DashMap::new();
+        let (leader, mut leader_receiver) = add_subscriber(&subscribers, 1);
+        let (_, mut other_receiver) = add_subscriber(&subscribers, 1);
+
+        send_to_subscribers(&subscribers, Some(&HashSet::from([leader])), &response(), 1);
+        assert!(leader_receiver.try_recv().is_ok());
+        assert!(other_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn counts_drops_for_full_queues_and_reports_closed_streams() {
+        let subscribers = DashMap::new();
+        let (slow, _slow_receiver) = add_subscriber(&subscribers, 1);
+        let (gone, gone_receiver) = add_subscriber(&subscribers, 1);
+        drop(gone_receiver);
+
+        send_to_subscribers(&subscribers, Some(&HashSet::from([slow])), &response(), 3);
+        let disconnected = send_to_subscribers(&subscribers, None, &response(), 3);
+
+        assert_eq!(disconnected, vec![gone]);
+        let stats = &subscribers.get(&slow).unwrap().stats;
+        assert_eq!(stats.packets_sent.load(Ordering::Relaxed), 3);
+        assert_eq!(stats.packets_dropped.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn totals_queue_depth_and_drops_across_subscribers() {
+        let subscribers = DashMap::new();
+        let (slow, _slow_receiver) = add_subscriber(&subscribers, 2);
+        let (_, _idle_receiver) = add_subscriber(&subscribers, 2);
+
+        for _ in 0..3 {
+            send_to_subscribers(&subscribers, Some(&HashSet::from([slow])), &response(), 1);
+        }
+
+        assert_eq!(
+            take_subscriber_totals(&subscribers),
+            SubscriberTotals {
+                packets_sent: 2,
+                packets_dropped: 1,
+                validators_dropping: 1,
+                queued_batches: 2,
+                max_queued_batches: 2,
+            }
+        );
+        // Counts reset each interval, queue depth does not.
+        assert_eq!(take_subscriber_totals(&subscribers).packets_sent, 0);
+        assert_eq!(take_subscriber_totals(&subscribers).queued_batches, 2);
+    }
+
+    #[test]
+    fn routes_to_connected_leaders_or_falls_back_to_all() {
+        let subscribers = DashMap::new();
+        let (connected, _receiver) = add_subscriber(&subscribers, 1);
+        let offline = Pubkey::new_unique();
+
+        assert_eq!(
+            connected_leaders(HashSet::from([connected, offline]), &subscribers),
+            Some(HashSet::from([connected]))
+        );
+        assert_eq!(
+            connected_leaders(HashSet::from([offline]), &subscribers),
+            None
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    collections::HashSet,
+    sync::{atomic::Ordering, RwLock},
+};
+
+use bincode::Options;
+use blocks::packet::{Packet, PacketBatch};
+use solana_sdk::{packet::PACKET_DATA_SIZE, pubkey::Pubkey, transaction::VersionedTransaction};
+
+use crate::fowardDelay::ForwarderStats;
+
+#[derive(Clone, Debug, Default)]
+pub struct FilterConfig {
+    pub verify_signatures: bool,
+    /// If non-empty, transactions must only invoke these programs.
+    pub program_allowlist: HashSet<Pubkey>,
+    /// Transactions invoking any of these programs are rejected.
+    pub program_denylist: HashSet<Pubkey>,
+}
+
+enum Rejection {
+    Malformed,
+    InvalidSignature,
+    DeniedProgram,
+    NotAllowlistedProgram,
+}
+
+/// Deserializes and checks each packet before it is forwarded, rejecting
+/// malformed transactions, bad signatures and unwanted programs, and marking
+/// vote transactions so they can be kept out of the block engine stream.
+///
+/// The program lists can be replaced while the filter is in use.
+#[derive(Debug)]
+pub struct PacketFilter {
+    config: RwLock<FilterConfig>,
+}
+
+impl PacketFilter {
+    pub fn new(config: FilterConfig) -> Self {
+        PacketFilter {
+            config: RwLock::new(config),
+        }
+    }
+
+    pub fn set_program_lists(
+        &self,
+        program_allowlist: HashSet<Pubkey>,
+        program_denylist: HashSet<Pubkey>,
+    ) {
+        let mut config = self.config.write().unwrap();
+        config.program_allowlist = program_allowlist;
+        config.program_denylist = program_denylist;
+    }
+
+    /// Returns the accepted packets with `is_vote` set, counting every
+    /// rejection by reason in `stats`.
+    pub fn filter(&self, batch: PacketBatch, stats: &ForwarderStats) -> PacketBatch {
+        let config = self.config.read().unwrap();
+        batch
+            .into_iter()
+            .filter_map(|mut packet| match Self::check(&config, &packet) {
+                Ok(is_vote) => {
+                    packet.meta.is_vote = is_vote;
+                    Some(packet)
+                }
+                Err(rejection) => {
+                    let counter = match rejection {
+                        Rejection::Malformed => &stats.rejected_malformed,
+                        Rejection::InvalidSignature => &stats.rejected_invalid_signature,
+                        Rejection::DeniedProgram => &stats.rejected_denied_program,
+                        Rejection::NotAllowlistedProgram => &stats.rejected_not_allowlisted_program,
+                    };
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn check(config: &FilterConfig, packet: &Packet) -> Result<bool, Rejection> {
+        let transaction: VersionedTransaction = bincode::DefaultOptions::new()
+            .with_limit(PACKET_DATA_SIZE as u64)
+            .with_fixint_encoding()
+            .reject_trailing_bytes()
+            .deserialize(packet.data())
+            .map_err(|_| Rejection::Malformed)?;
+        transaction.sanitize().map_err(|_| Rejection::Malformed)?;
+
+        if config.verify_signatures && !transaction.verify_with_results().into_iter().all(|ok| ok) {
+            return Err(Rejection::InvalidSignature);
+        }
+
+        let account_keys = transaction.message.static_account_keys();
+        let mut is_vote = false;
+        for instruction in transaction.message.instructions() {
+            let program_id = account_keys
+                .get(instruction.program_id_index as usize)
+                .ok_or(Rejection::Malformed)?;
+            if config.program_denylist.contains(program_id) {
+                return Err(Rejection::DeniedProgram);
+            }
+            if !config.program_allowlist.is_empty()
+                && !config.program_allowlist.contains(program_id)
+            {
+                return Err(Rejection::NotAllowlistedProgram);
+            }
+            is_vote |= solana_sdk::vote::program::check_id(program_id);
+        }
+        Ok(is_vote)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{
+        hash::Hash,
+        instruction::Instruction,
+        signature::{Keypair, Signer},
+        system_program,
+        transaction::Transaction,
+        vote,
+    };
+
+    use super::*;
+
+    fn packet(data: Vec<u8>) -> Packet {
+        Packet::new(data, "127.0.0.1:0".parse().unwrap(), false)
+    }
+
+    fn invoking(program_id: Pubkey) -> Vec<u8> {
+        let payer = Keypair::new();
+        let transaction = Transaction::new_signed_with_payer(
+            &[Instruction::new_with_bytes(program_id, &[], vec![])],
+            Some(&payer.pubkey()),
+            &[&payer],
+            Hash::default(),
+        );
+        bincode::serialize(&transaction).unwrap()
+    }
+
+    fn filter(config: FilterConfig, data: Vec<u8>) -> (PacketBatch, ForwarderStats) {
+        let stats = ForwarderStats::default();
+        let batch = PacketFilter::new(config).filter(PacketBatch::new(vec![packet(data)]), &stats);
+        (batch, stats)
+    }
+
+    #[test]
+    fn rejects_malformed_and_trailing_bytes() {
+        let (batch, stats) = filter(FilterConfig::default(), vec![1, 2, 3]);
+        assert!(batch.is_empty());
+        assert_eq!(stats.rejected_malformed.load(Ordering::Relaxed), 1);
+
+        let mut data = invoking(system_program::id());
+        data.push(0);
+        let (batch, stats) = filter(FilterConfig::default(), data);
+        assert!(batch.is_empty());
+        assert_eq!(stats.rejected_malformed.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_signatures_when_verifying() {
+        let mut data = invoking(system_program::id());
+        // The first signature starts right after its one-byte count.
+        data[1] ^= 0xff;
+        let config = FilterConfig {
+            verify_signatures: true,
+            ..FilterConfig::default()
+        };
+        let (batch, stats) = filter(config, data.clone());
+        assert!(batch.is_empty());
+        assert_eq!(stats.rejected_invalid_signature.load(Ordering::Relaxed), 1);
+
+        let (batch, _) = filter(FilterConfig::default(), data);
+        assert_eq!(batch.len(), 1);
+    }
+
+    #[test]
+    fn applies_program_denylist_and_allowlist() {
+        let denied = Pubkey::new_unique();
+        let config = FilterConfig {
+            program_denylist: HashSet::from([denied]),
+            ..FilterConfig::default()
+        };
+        let (batch, stats) = filter(config, invoking(denied));
+        assert!(batch.is_empty());
+        assert_eq!(stats.rejected_denied_program.load(Ordering::Relaxed), 1);
+
+        let config = FilterConfig {
+            program_allowlist: HashSet::from([system_program::id()]),
+            ..FilterConfig::default()
+        };
+        let (batch, _) = filter(config.clone(), invoking(system_program::id()));
+        assert_eq!(batch.len(), 1);
+        let (batch, stats) = filter(config, invoking(Pubkey::new_unique()));
+        assert!(batch.is_empty());
+        assert_eq!(
+            stats
+                .rejected_not_allowlisted_program
+                .load(Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[test]
+    fn program_lists_can_be_replaced() {
+        let program = Pubkey::new_unique();
+        let filter = PacketFilter::new(FilterConfig::default());
+        let stats = ForwarderStats::default();
+        let batch = || PacketBatch::new(vec![packet(invoking(program))]);
+        assert_eq!(filter.filter(batch(), &stats).len(), 1);
+
+        filter.set_program_lists(HashSet::new(), HashSet::from([program]));
+        assert!(filter.filter(batch(), &stats).is_empty());
+
+        filter.set_program_lists(HashSet::from([program]), HashSet::new());
+        assert_eq!(filter.filter(batch(), &stats).len(), 1);
+    }
+
+    #[test]
+    fn marks_vote_transactions() {
+        let (batch, _) = filter(FilterConfig::default(), invoking(vote::program::id()));
+        assert!(batch.iter().next().unwrap().meta.is_vote);
+
+        let (batch, _) = filter(FilterConfig::default(), invoking(system_program::id()));
+        assert!(!batch.iter().next().unwrap().meta.is_vote);
+    }
+}
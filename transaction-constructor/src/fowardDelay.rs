@@ -1,79 +1,527 @@
 use std::{
-    collections::VecDeque,
+    io,
+    path::PathBuf,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, RwLock,
     },
-    thread::{Builder, JoinHandle},
-    time::{Duration, Instant, SystemTime},
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
+use blocks::{
+    metrics::{LatencyHistogram, MetricsSink},
+    packet::PacketBatch,
+    supervisor::spawn_supervised,
+};
+use builder_block::health::HealthState;
 use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 use log::{error, info, warn};
-tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::{
+    capture::{start_capture, CaptureSender, DEFAULT_CAPTURE_MAX_BYTES},
+    dedup::{DedupConfig, Deduper},
+    delay_policy::DelayPolicy,
+    delay_queue::{DelayQueue, DropPolicy, PushOutcome},
+    filter::PacketFilter,
+};
 
 pub const FORWARDER_QUEUE_CAPACITY: usize = 5_000;
+/// Largest batch the release thread sends to validators at once.
+const MAX_RELEASE_BATCH_SIZE: usize = 64;
+const SLEEP_DURATION: Duration = Duration::from_millis(5);
+
+#[derive(Clone, Debug)]
+pub struct ForwarderConfig {
+    pub num_threads: u64,
+    pub disable_mempool: bool,
+    /// Maximum number of packets waiting out their delay.
+    pub queue_capacity: usize,
+    pub drop_policy: DropPolicy,
+    /// Drop repeated transactions before forwarding; `None` disables.
+    pub dedup: Option<DedupConfig>,
+    /// Sanitize and filter transactions before forwarding; `None` disables.
+    /// Shared so its program lists can be reloaded.
+    pub filter: Option<Arc<PacketFilter>>,
+    /// How long delayed packets keep being released after `exit` is set,
+    /// and how long workers keep retrying a full queue under backpressure.
+    pub drain_timeout: Duration,
+    /// File every received batch is recorded to for replay; `None` disables.
+    pub capture_path: Option<PathBuf>,
+    /// Size at which the capture file stops growing; 0 means no limit.
+    pub capture_max_bytes: u64,
+}
+
+impl Default for ForwarderConfig {
+    fn default() -> Self {
+        ForwarderConfig {
+            num_threads: 1,
+            disable_mempool: false,
+            queue_capacity: FORWARDER_QUEUE_CAPACITY,
+            drop_policy: DropPolicy::default(),
+            dedup: Some(DedupConfig {
+                ttl_secs: 2,
+                capacity: 100_000,
+            }),
+            filter: None,
+            drain_timeout: Duration::from_secs(1),
+            capture_path: None,
+            capture_max_bytes: DEFAULT_CAPTURE_MAX_BYTES,
+        }
+    }
+}
+
+/// Forwarder metrics for the current reporting interval, shared by every
+/// forwarder thread.
+#[derive(Default)]
+pub struct ForwarderStats {
+    pub packets_received: AtomicU64,
+    /// Packets dropped because their signature was seen within the TTL.
+    pub duplicates_dropped: AtomicU64,
+    pub rejected_malformed: AtomicU64,
+    pub rejected_invalid_signature: AtomicU64,
+    pub rejected_denied_program: AtomicU64,
+    pub rejected_not_allowlisted_program: AtomicU64,
+    /// Vote transactions kept out of the block engine stream.
+    pub votes_withheld_from_engine: AtomicU64,
+    /// Packets sent to the block engine.
+    pub packets_forwarded: AtomicU64,
+    pub block_engine_queue_full: AtomicU64,
+    /// Packets released to validators after their delay.
+    pub packets_delayed: AtomicU64,
+    /// Packets discarded because the delay queue was full or validators
+    /// were disconnected.
+    pub packets_dropped: AtomicU64,
+    /// Delayed packets withheld from validators while the relayer was
+    /// unhealthy.
+    pub packets_dropped_unhealthy: AtomicU64,
+    /// Batches left out of the capture file because its writer fell
+    /// behind.
+    pub capture_dropped: AtomicU64,
+    pub delay_queue_depth: AtomicU64,
+    /// How long after its intended release time each delayed packet was
+    /// actually released.
+    pub release_lateness_us: LatencyHistogram,
+}
+
+impl ForwarderStats {
+    /// Submits the interval's values to `sink` and resets the counters.
+    pub fn report(&self, sink: &dyn MetricsSink) {
+        sink.submit(
+            "forwarder_stats",
+            &[
+                (
+                    "packets_received",
+                    self.packets_received.swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "duplicates_dropped",
+                    self.duplicates_dropped.swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "rejected_malformed",
+                    self.rejected_malformed.swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "rejected_invalid_signature",
+                    self.rejected_invalid_signature.swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "rejected_denied_program",
+                    self.rejected_denied_program.swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "rejected_not_allowlisted_program",
+                    self.rejected_not_allowlisted_program
+                        .swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "votes_withheld_from_engine",
+                    self.votes_withheld_from_engine.swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "packets_forwarded",
+                    self.packets_forwarded.swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "block_engine_queue_full",
+                    self.block_engine_queue_full.swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "packets_delayed",
+                    self.packets_delayed.swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "packets_dropped",
+                    self.packets_dropped.swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "packets_dropped_unhealthy",
+                    self.packets_dropped_unhealthy.swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "capture_dropped",
+                    self.capture_dropped.swap(0, Ordering::Relaxed),
+                ),
+            ],
+        );
+        sink.submit_gauges(
+            "forwarder_stats",
+            &[(
+                "delay_queue_depth",
+                self.delay_queue_depth.load(Ordering::Relaxed),
+            )],
+        );
+        sink.submit_histogram(
+            "forwarder_stats",
+            "release_lateness_us",
+            &self.release_lateness_us.take(),
+        );
+    }
+}
 
 /// Handles forwarding and delaying packets before they reach the validator.
+///
+/// `num_threads` workers receive verified packet batches, reject packets
+/// the filter fails, drop transactions already seen from another TPU
+/// connection, forward the rest to the block engine and push each packet
+/// onto one shared [`DelayQueue`], held for as long as `delay_policy`
+/// decides. A single release thread drains that
+/// queue in batches, so packets with equal delays leave in arrival order no
+/// matter which worker received them.
+///
+/// Released packets are dropped rather than sent to validators while
+/// `health_state` is unhealthy, since the relayer's view of the chain is
+/// stale.
+///
+/// Once `exit` is set, workers finish the batches already received while the
+/// release thread keeps releasing as usual. Only after the last worker has
+/// returned does it drain the queue, for up to `config.drain_timeout`, so no
+/// packet is pushed after the drain has ended. Workers that panic are
+/// restarted.
+///
+/// With `config.capture_path` set, every batch is also recorded, as received,
+/// to a capture file the `replay` binary can feed back through this stage.
+/// Failing to create that file is the only error returned.
 pub fn start_forward_and_delay_thread(
-    verified_receiver: Receiver<Vec<u8>>, 
-    delay_packet_sender: Sender<Vec<u8>>, 
-    packet_delay_ms: u32,
-    block_engine_sender: tokio::sync::mpsc::Sender<Vec<u8>>, 
-    num_threads: u64,
-    disable_mempool: bool,
+    verified_receiver: Receiver<PacketBatch>,
+    delay_packet_sender: Sender<PacketBatch>,
+    block_engine_sender: tokio::sync::mpsc::Sender<PacketBatch>,
+    delay_policy: Arc<dyn DelayPolicy>,
+    health_state: Arc<RwLock<HealthState>>,
+    config: ForwarderConfig,
+    stats: &Arc<ForwarderStats>,
+    metrics_sink: Arc<dyn MetricsSink>,
     exit: &Arc<AtomicBool>,
-) -> Vec<JoinHandle<()>> {
-    const SLEEP_DURATION: Duration = Duration::from_millis(5);
-    let packet_delay = Duration::from_millis(packet_delay_ms as u64);
+) -> io::Result<Vec<JoinHandle<()>>> {
+    let delay_queue = Arc::new(DelayQueue::new(config.queue_capacity, config.drop_policy));
+    let deduper = config.dedup.map(|dedup| Arc::new(Deduper::new(dedup)));
+    let filter = config.filter.clone();
+    let (capture, capture_thread) = match &config.capture_path {
+        Some(path) => {
+            let (capture, thread) = start_capture(path, config.capture_max_bytes).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("failed to create packet capture {}: {e}", path.display()),
+                )
+            })?;
+            (Some(capture), Some(thread))
+        }
+        None => (None, None),
+    };
 
-    (0..num_threads)
+    let live_workers = Arc::new(AtomicUsize::new(config.num_threads as usize));
+    let mut threads: Vec<JoinHandle<()>> = (0..config.num_threads)
         .map(|thread_id| {
-            let verified_receiver = verified_receiver.clone();
-            let delay_packet_sender = delay_packet_sender.clone();
-            let block_engine_sender = block_engine_sender.clone();
-            let exit = exit.clone();
-            
-            Builder::new()
-                .name(format!("forwarder_thread_{thread_id}"))
-                .spawn(move || {
-                    let mut buffered_packets: VecDeque<(Instant, Vec<u8>)> = VecDeque::new();
-                    let metrics_interval = Duration::from_secs(1);
-                    let mut last_metrics_upload = Instant::now();
-
-                    while !exit.load(Ordering::Relaxed) {
-                        if last_metrics_upload.elapsed() >= metrics_interval {
-                            last_metrics_upload = Instant::now();
-                        }
+            let mut worker = ForwardWorker {
+                _live: LiveWorker(live_workers.clone()),
+                verified_receiver: verified_receiver.clone(),
+                block_engine_sender: block_engine_sender.clone(),
+                forward_to_engine: !config.disable_mempool,
+                deduper: deduper.clone(),
+                filter: filter.clone(),
+                capture: capture.clone(),
+                delay_queue: delay_queue.clone(),
+                delay_policy: delay_policy.clone(),
+                stats: stats.clone(),
+                exit: exit.clone(),
+                drain_timeout: config.drain_timeout,
+            };
+            spawn_supervised(
+                format!("forwarder_thread_{thread_id}"),
+                exit.clone(),
+                move || worker.run(),
+            )
+        })
+        .collect();
 
-                        match verified_receiver.recv_timeout(SLEEP_DURATION) {
-                            Ok(packet) => {
-                                let instant = Instant::now();
-                                let system_time = SystemTime::now();
-                                
-                                if !disable_mempool {
-                                    match block_engine_sender.try_send(packet.clone()) {
-                                        Ok(_) => {},
-                                        Err(TrySendError::Closed(_)) => panic!("Error sending to block engine"),
-                                        Err(TrySendError::Full(_)) => warn!("Block engine queue full"),
-                                    }
-                                }
-                                buffered_packets.push_back((instant, packet));
-                            }
-                            Err(RecvTimeoutError::Timeout) => {}
-                            Err(RecvTimeoutError::Disconnected) => panic!("Receiver disconnected"),
+    // The capture thread exits once the workers drop their senders.
+    drop(capture);
+    threads.extend(capture_thread);
+
+    let stats = stats.clone();
+    let drain_timeout = config.drain_timeout;
+    threads.push(spawn_supervised(
+        "delay_release_thread".into(),
+        exit.clone(),
+        move || {
+            release_delayed_packets(
+                &delay_queue,
+                &delay_packet_sender,
+                &health_state,
+                &stats,
+                metrics_sink.as_ref(),
+                drain_timeout,
+                &live_workers,
+            )
+        },
+    ));
+
+    Ok(threads)
+}
+
+/// Counts a worker as live until its thread ends, whether `run` returned or
+/// a panic was not restarted.
+struct LiveWorker(Arc<AtomicUsize>);
+
+impl Drop for LiveWorker {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Release);
+    }
+}
+
+struct ForwardWorker {
+    _live: LiveWorker,
+    verified_receiver: Receiver<PacketBatch>,
+    block_engine_sender: tokio::sync::mpsc::Sender<PacketBatch>,
+    forward_to_engine: bool,
+    deduper: Option<Arc<Deduper>>,
+    filter: Option<Arc<PacketFilter>>,
+    capture: Option<CaptureSender>,
+    delay_queue: Arc<DelayQueue>,
+    delay_policy: Arc<dyn DelayPolicy>,
+    stats: Arc<ForwarderStats>,
+    exit: Arc<AtomicBool>,
+    drain_timeout: Duration,
+}
+
+impl ForwardWorker {
+    /// Processes batches until the TPU closes the verified channel, or until
+    /// `exit` is set and the channel is empty.
+    fn run(&mut self) {
+        loop {
+            match self.verified_receiver.recv_timeout(SLEEP_DURATION) {
+                Ok(batch) => {
+                    self.stats
+                        .packets_received
+                        .fetch_add(batch.len() as u64, Ordering::Relaxed);
+                    if let Some(capture) = &self.capture {
+                        if !capture.capture(&batch) {
+                            self.stats.capture_dropped.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    // Filter first, so a malformed or forged copy can't
+                    // enter the dedup cache and suppress the real
+                    // transaction.
+                    let batch = match &self.filter {
+                        Some(filter) => filter.filter(batch, &self.stats),
+                        None => batch,
+                    };
+                    let batch = match &self.deduper {
+                        Some(deduper) => {
+                            let (batch, duplicates) = deduper.dedup(batch);
+                            self.stats
+                                .duplicates_dropped
+                                .fetch_add(duplicates, Ordering::Relaxed);
+                            batch
                         }
+                        None => batch,
+                    };
+                    if batch.is_empty() {
+                        continue;
+                    }
+                    if self.forward_to_engine {
+                        self.forward_to_block_engine(&batch);
+                    }
+                    self.delay(batch);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.exit.load(Ordering::Relaxed) {
+                        return;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    info!("Verified packet channel closed, forwarder stopping");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Sends a copy of the batch, minus vote transactions, to the block
+    /// engine. Votes are recognised when each packet is created, whether or
+    /// not the filter is enabled. Only packet handles are cloned; payloads
+    /// are shared with the delayed copy.
+    fn forward_to_block_engine(&mut self, batch: &PacketBatch) {
+        let engine_batch: PacketBatch = batch.iter().filter(|p| !p.meta.is_vote).cloned().collect();
+        self.stats
+            .votes_withheld_from_engine
+            .fetch_add((batch.len() - engine_batch.len()) as u64, Ordering::Relaxed);
+        if engine_batch.is_empty() {
+            return;
+        }
+        let num_packets = engine_batch.len() as u64;
+        match self.block_engine_sender.try_send(engine_batch) {
+            Ok(_) => {
+                self.stats
+                    .packets_forwarded
+                    .fetch_add(num_packets, Ordering::Relaxed);
+            }
+            Err(TrySendError::Closed(_)) => {
+                error!("Block engine channel closed, no longer forwarding to it");
+                self.forward_to_engine = false;
+            }
+            Err(TrySendError::Full(_)) => {
+                self.stats
+                    .block_engine_queue_full
+                    .fetch_add(1, Ordering::Relaxed);
+                warn!("Block engine queue full")
+            }
+        }
+    }
 
-                        while let Some((timestamp, packet)) = buffered_packets.front() {
-                            if timestamp.elapsed() < packet_delay {
+    /// Pushes each packet onto the delay queue. Under backpressure a full
+    /// queue is retried until there is room, or, once `exit` is set, for up
+    /// to `drain_timeout`, after which the packet is counted as dropped.
+    fn delay(&self, batch: PacketBatch) {
+        let mut give_up_at = None;
+        for packet in batch {
+            let delay = self.delay_policy.delay(&packet);
+            let mut packet = packet;
+            loop {
+                match self.delay_queue.push(packet, delay, SLEEP_DURATION) {
+                    PushOutcome::Queued => break,
+                    PushOutcome::Dropped => {
+                        self.stats.packets_dropped.fetch_add(1, Ordering::Relaxed);
+                        break;
+                    }
+                    // The release thread keeps freeing space until every
+                    // worker has returned, but a stalled one must not hold
+                    // up shutdown forever.
+                    PushOutcome::Full(returned) => {
+                        if self.exit.load(Ordering::Relaxed) {
+                            let deadline = *give_up_at
+                                .get_or_insert_with(|| Instant::now() + self.drain_timeout);
+                            if Instant::now() >= deadline {
+                                self.stats.packets_dropped.fetch_add(1, Ordering::Relaxed);
                                 break;
                             }
-                            let _ = buffered_packets.pop_front();
-                            delay_packet_sender.send(packet.clone()).expect("Failed to send delayed packet");
                         }
+                        packet = returned;
                     }
-                })
-                .unwrap()
-        })
-        .collect()
+                }
+            }
+        }
+    }
+}
+
+/// Releases due packets until every worker has returned, then keeps
+/// releasing for up to `drain_timeout` so queued packets still reach
+/// validators; whatever is left after that is counted as dropped.
+fn release_delayed_packets(
+    delay_queue: &DelayQueue,
+    delay_packet_sender: &Sender<PacketBatch>,
+    health_state: &RwLock<HealthState>,
+    stats: &ForwarderStats,
+    metrics_sink: &dyn MetricsSink,
+    drain_timeout: Duration,
+    live_workers: &AtomicUsize,
+) {
+    let metrics_interval = Duration::from_secs(1);
+    let mut last_metrics_upload = Instant::now();
+    let mut validators_connected = true;
+
+    while live_workers.load(Ordering::Acquire) > 0 {
+        if last_metrics_upload.elapsed() >= metrics_interval {
+            stats
+                .delay_queue_depth
+                .store(delay_queue.len() as u64, Ordering::Relaxed);
+            stats.report(metrics_sink);
+            last_metrics_upload = Instant::now();
+        }
+        release_due(
+            delay_queue,
+            delay_packet_sender,
+            health_state,
+            stats,
+            &mut validators_connected,
+        );
+    }
+
+    let drain_deadline = Instant::now() + drain_timeout;
+    info!(
+        "Draining {} delayed packets before shutdown",
+        delay_queue.len()
+    );
+    while !delay_queue.is_empty() && Instant::now() < drain_deadline {
+        release_due(
+            delay_queue,
+            delay_packet_sender,
+            health_state,
+            stats,
+            &mut validators_connected,
+        );
+    }
+    let remaining = delay_queue.len();
+    if remaining > 0 {
+        warn!("Dropping {remaining} delayed packets not released before the drain deadline");
+    }
+    stats
+        .packets_dropped
+        .fetch_add(remaining as u64, Ordering::Relaxed);
+    stats.delay_queue_depth.store(0, Ordering::Relaxed);
+    stats.report(metrics_sink);
+}
+
+fn release_due(
+    delay_queue: &DelayQueue,
+    delay_packet_sender: &Sender<PacketBatch>,
+    health_state: &RwLock<HealthState>,
+    stats: &ForwarderStats,
+    validators_connected: &mut bool,
+) {
+    let due = delay_queue.pop_due(SLEEP_DURATION, MAX_RELEASE_BATCH_SIZE);
+    if due.is_empty() {
+        return;
+    }
+    let released = Instant::now();
+    let num_packets = due.len() as u64;
+    let mut batch = PacketBatch::with_capacity(due.len());
+    for (due_at, packet) in due {
+        stats
+            .release_lateness_us
+            .record(released.saturating_duration_since(due_at).as_micros() as u64);
+        batch.push(packet);
+    }
+
+    if matches!(*health_state.read().unwrap(), HealthState::Unhealthy) {
+        stats
+            .packets_dropped_unhealthy
+            .fetch_add(num_packets, Ordering::Relaxed);
+        return;
+    }
+    if *validators_connected && delay_packet_sender.send(batch).is_ok() {
+        stats
+            .packets_delayed
+            .fetch_add(num_packets, Ordering::Relaxed);
+        return;
+    }
+    if *validators_connected {
+        error!("Delayed packet receiver disconnected, dropping delayed packets");
+        *validators_connected = false;
+    }
+    stats
+        .packets_dropped
+        .fetch_add(num_packets, Ordering::Relaxed);
 }
@@ -0,0 +1,75 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+use builder_block::health::HealthState;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::{error, info};
+
+use crate::prometheus::PrometheusMetricsSink;
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+#[derive(Clone)]
+pub struct HttpState {
+    pub prometheus: Arc<PrometheusMetricsSink>,
+    pub health_state: Arc<RwLock<HealthState>>,
+}
+
+/// Serves operational HTTP endpoints until `shutdown` completes:
+///
+/// - `GET /metrics`: Prometheus metrics
+/// - `GET /health`: `200 OK` while the relayer is healthy, `503` otherwise
+pub async fn serve(addr: SocketAddr, state: HttpState, shutdown: impl Future<Output = ()>) {
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = handle(&request, &state);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    info!("Serving metrics and health at: {:?}", addr);
+    let server = match Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            error!("Failed to bind HTTP server to {addr}: {e}");
+            return;
+        }
+    };
+    if let Err(e) = server.with_graceful_shutdown(shutdown).await {
+        error!("HTTP server error: {e}");
+    }
+}
+
+fn handle(request: &Request<Body>, state: &HttpState) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)
+            .body(Body::from(state.prometheus.render()))
+            .unwrap(),
+        (&Method::GET, "/health") => {
+            let (status, body) = match *state.health_state.read().unwrap() {
+                HealthState::Healthy => (StatusCode::OK, "healthy"),
+                HealthState::Unhealthy => (StatusCode::SERVICE_UNAVAILABLE, "unhealthy"),
+            };
+            Response::builder()
+                .status(status)
+                .body(Body::from(body))
+                .unwrap()
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+    }
+}
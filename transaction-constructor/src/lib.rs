@@ -1 +1,12 @@
+pub mod capture;
+pub mod config;
+pub mod dedup;
+pub mod delay_policy;
+pub mod delay_queue;
+pub mod fanout;
+pub mod filter;
 pub mod fowardDelay;
+pub mod http_server;
+pub mod logging;
+pub mod prometheus;
+pub mod validator_auth;
@@ -0,0 +1,100 @@
+//! Logger whose level for the relayer's own modules can be changed while it
+//! runs, without raising the level of its dependencies.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use env_logger::Env;
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Crates filtered by the reloadable level; every other target is filtered
+/// by `RUST_LOG`, `info` by default.
+const OWN_CRATES: &[&str] = &["transaction_constructor", "blocks"];
+
+/// Changes the level of the relayer's own modules.
+#[derive(Clone)]
+pub struct LogLevelHandle {
+    level: Arc<AtomicUsize>,
+    dependency_level: LevelFilter,
+}
+
+impl LogLevelHandle {
+    pub fn set(&self, level: LevelFilter) {
+        self.level.store(level as usize, Ordering::Relaxed);
+        log::set_max_level(level.max(self.dependency_level));
+    }
+}
+
+struct ReloadableLogger {
+    inner: env_logger::Logger,
+    level: Arc<AtomicUsize>,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if is_own_target(metadata.target()) {
+            metadata.level() as usize <= self.level.load(Ordering::Relaxed)
+        } else {
+            self.inner.enabled(metadata)
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+fn is_own_target(target: &str) -> bool {
+    OWN_CRATES.iter().any(|name| {
+        target
+            .strip_prefix(name)
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+    })
+}
+
+/// Installs the global logger with `level` for the relayer's own modules
+/// and returns the handle that changes it.
+pub fn init(level: LevelFilter) -> LogLevelHandle {
+    let env = || Env::new().default_filter_or("info");
+    let dependency_level = env_logger::Builder::from_env(env()).build().filter();
+    // The inner logger admits everything from our own crates; the handle's
+    // level does that filtering instead.
+    let mut builder = env_logger::Builder::from_env(env());
+    for name in OWN_CRATES {
+        builder.filter_module(name, LevelFilter::Trace);
+    }
+    let handle = LogLevelHandle {
+        level: Arc::new(AtomicUsize::new(level as usize)),
+        dependency_level,
+    };
+    log::set_boxed_logger(Box::new(ReloadableLogger {
+        inner: builder.build(),
+        level: handle.level.clone(),
+    }))
+    .expect("logger already initialized");
+    handle.set(level);
+    handle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_own_crates_and_their_modules_only() {
+        assert!(is_own_target("transaction_constructor"));
+        assert!(is_own_target("transaction_constructor::fanout"));
+        assert!(is_own_target("blocks::block_relayer"));
+        assert!(!is_own_target("blocks_extra"));
+        assert!(!is_own_target("hyper::proto::h1"));
+        assert!(!is_own_target("h2"));
+    }
+}
@@ -1,40 +1,110 @@
+use blocks::{
+    block_relayer::{EngineConfig, EngineRelayerHandler},
+    metrics::{LogMetricsSink, MetricsSink, MetricsSinks},
+    packet::{Packet, PacketBatch},
+    supervisor::spawn_supervised,
+};
 use builder_block::{
-    auth::{AuthServiceImpl, ValidatorAuther},
-    health::HealthManager,
+    auth::AuthServiceImpl,
+    health::{HealthManager, HealthState},
     network::{get_public_ip_addr, multi_bind_in_range},
-    relayer::{LeaderScheduleCacheUpdater, RelayerImpl},
+    proto::relayer::relayer_server::RelayerServer,
+    relayer::{LeaderScheduleCacheUpdater, LeaderScheduleUpdatingHandle},
     rpc::LoadBalancer,
-    tpu::{Tpu, TpuSockets},
+    tpu::{BankingPacketBatch, Tpu, TpuSockets},
 };
-use clap::Parser;
-use crossbeam_channel::tick;
-use dashmap::DashMap;
-use env_logger::Env;
-use log::{debug, error, info, warn};
-use openssl::{hash::MessageDigest, pkey::PKey};
+use clap::{builder::RangedU64ValueParser, error::ErrorKind, CommandFactory, Parser, ValueEnum};
+use crossbeam_channel::{Receiver, Sender};
+use log::{error, info, warn, LevelFilter};
 use solana_sdk::{
     pubkey::Pubkey,
     signature::{read_keypair_file, Signer},
 };
 use std::{
-    collections::HashSet,
-    fs,
+    env, fs,
     net::{IpAddr, Ipv4Addr, SocketAddr},
-    ops::Range,
-    path::PathBuf,
-    str::FromStr,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, RwLock,
     },
     thread,
-    time::{Duration, Instant},
+    time::Duration,
+};
+use tokio::{
+    runtime::Builder,
+    signal::{self, unix::SignalKind},
+    time::sleep,
 };
-use tokio::{runtime::Builder, signal};
-use tonic::transport::Server;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity, Server, ServerTlsConfig};
+use tonic_health::{server::HealthReporter, ServingStatus};
+use transaction_constructor::{
+    capture::DEFAULT_CAPTURE_MAX_BYTES,
+    config,
+    dedup::DedupConfig,
+    delay_policy::{
+        AllowlistDelay, DelayPolicy, FixedDelay, LeaderProximityDelay, ScheduleProximity,
+        SwitchableDelayPolicy,
+    },
+    delay_queue::DropPolicy,
+    fanout::{
+        FanoutConfig, FanoutRelayer, PacketFanout, LEADER_LOOKAHEAD_SLOTS,
+        SUBSCRIBER_QUEUE_CAPACITY,
+    },
+    filter::{FilterConfig, PacketFilter},
+    fowardDelay::{
+        start_forward_and_delay_thread, ForwarderConfig, ForwarderStats, FORWARDER_QUEUE_CAPACITY,
+    },
+    http_server::{self, HttpState},
+    logging::{self, LogLevelHandle},
+    prometheus::PrometheusMetricsSink,
+    validator_auth::{AllowlistAuther, StakePolicy, ValidatorAccessConfig},
+};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum DelayPolicyKind {
+    /// Hold every packet for --packet-delay-ms.
+    Fixed,
+    /// Shorten the delay as a --leader-proximity-validators leader slot nears.
+    LeaderProximity,
+}
 
 #[derive(Parser, Debug)]
 struct Args {
+    /// YAML file of settings keyed by flag name in snake_case, e.g.
+    /// `packet_delay_ms: 150`. Flags and environment variables override it.
+    /// On SIGHUP the file is re-read and the delay settings, zero-delay
+    /// signers, program allowlist and denylist, and log level are applied
+    /// without a restart.
+    #[arg(long, env)]
+    config: Option<PathBuf>,
+
+    /// Log level for the relayer's own modules. Dependencies are filtered
+    /// by RUST_LOG, which defaults to info.
+    #[arg(long, env, default_value_t = LevelFilter::Info)]
+    log_level: LevelFilter,
+
+    /// Address the relayer's gRPC server binds to.
+    #[arg(long, env, default_value_t = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 11_226))]
+    grpc_bind_addr: SocketAddr,
+
+    /// Public IP to advertise instead of discovering it; required when
+    /// discovery fails.
+    #[arg(long, env)]
+    public_ip: Option<IpAddr>,
+
+    /// PEM certificate for serving gRPC over TLS.
+    #[arg(long, env, requires = "tls_key_path")]
+    tls_cert_path: Option<PathBuf>,
+
+    #[arg(long, env, requires = "tls_cert_path")]
+    tls_key_path: Option<PathBuf>,
+
+    /// PEM CA bundle; when set, gRPC clients must present a certificate
+    /// signed by it.
+    #[arg(long, env, requires = "tls_cert_path")]
+    tls_client_ca_path: Option<PathBuf>,
+
     #[arg(long, env, default_value_t = 11_228)]
     tpu_quic_port: u16,
 
@@ -47,52 +117,688 @@ struct Args {
     #[arg(long, env, value_delimiter = ' ')]
     rpc_servers: Vec<String>,
 
+    /// Websocket URLs for slot subscriptions, one per --rpc-servers entry
+    /// and in the same order.
     #[arg(long, env, value_delimiter = ' ')]
     websocket_servers: Vec<String>,
+
+    /// How long without a new slot before the relayer reports itself
+    /// unhealthy and stops forwarding to validators.
+    #[arg(long, env, default_value_t = 10)]
+    missing_slot_unhealthy_secs: u64,
+
+    /// How long packets are held before being forwarded to validators, or the
+    /// longest delay under the leader-proximity policy.
+    #[arg(long, env, default_value_t = 200)]
+    packet_delay_ms: u32,
+
+    #[arg(long, env, value_enum, default_value_t = DelayPolicyKind::Fixed)]
+    delay_policy: DelayPolicyKind,
+
+    /// Signers whose packets are forwarded to validators without delay.
+    #[arg(long, env, value_delimiter = ' ')]
+    zero_delay_signers: Vec<Pubkey>,
+
+    /// Validators whose upcoming leader slots drive the leader-proximity delay.
+    #[arg(long, env, value_delimiter = ' ')]
+    leader_proximity_validators: Vec<Pubkey>,
+
+    /// Delay used when a leader-proximity validator is the current leader;
+    /// it grows to --packet-delay-ms over the lookahead window.
+    #[arg(long, env, default_value_t = 0)]
+    near_leader_packet_delay_ms: u32,
+
+    #[arg(long, env, default_value_t = 32)]
+    leader_proximity_lookahead_slots: u64,
+
+    /// Number of threads forwarding and delaying verified packets.
+    #[arg(long, env, default_value_t = 1)]
+    forwarder_threads: u64,
+
+    /// Maximum number of packets held in the delay queue.
+    #[arg(
+        long,
+        env,
+        default_value_t = FORWARDER_QUEUE_CAPACITY,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    forwarder_queue_capacity: usize,
+
+    /// What to do with new packets when the delay queue is full.
+    #[arg(long, env, value_enum, default_value_t = DropPolicy::DropOldest)]
+    forwarder_drop_policy: DropPolicy,
+
+    /// How long a transaction signature is remembered for deduplication;
+    /// 0 disables deduplication.
+    #[arg(long, env, default_value_t = 2)]
+    dedup_ttl_secs: u64,
+
+    /// Maximum number of signatures remembered for deduplication.
+    #[arg(long, env, default_value_t = 100_000)]
+    dedup_capacity: usize,
+
+    /// Deserialize and check every packet before forwarding it. Takes
+    /// effect on restart; the program lists are reloaded on SIGHUP.
+    #[arg(long, env, default_value_t = false)]
+    filter_packets: bool,
+
+    /// Don't verify transaction signatures when filtering packets.
+    #[arg(long, env, default_value_t = false)]
+    filter_skip_signature_verification: bool,
+
+    /// When filtering, only forward transactions that invoke these programs.
+    #[arg(long, env, value_delimiter = ' ')]
+    program_allowlist: Vec<Pubkey>,
+
+    /// When filtering, reject transactions that invoke any of these programs.
+    #[arg(long, env, value_delimiter = ' ')]
+    program_denylist: Vec<Pubkey>,
+
+    /// File of validator identity pubkeys allowed to connect, one per line;
+    /// reloaded when it changes. Any validator may connect if unset.
+    #[arg(long, env)]
+    validator_allowlist_path: Option<PathBuf>,
+
+    /// Minimum activated stake a validator needs to connect; 0 disables.
+    #[arg(long, env, default_value_t = 0)]
+    min_validator_stake_lamports: u64,
+
+    /// What to do with validators below --min-validator-stake-lamports.
+    #[arg(long, env, value_enum, default_value_t = StakePolicy::Reject)]
+    below_min_stake: StakePolicy,
+
+    #[arg(long, env, default_value_t = 1)]
+    below_min_stake_auths_per_minute: u32,
+
+    /// Packet batches buffered per connected validator before new batches
+    /// are dropped for it.
+    #[arg(
+        long,
+        env,
+        default_value_t = SUBSCRIBER_QUEUE_CAPACITY,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    subscriber_queue_capacity: usize,
+
+    /// Send packets only to connected validators leading within this many
+    /// slots, falling back to all validators when none is. 0 sends every
+    /// packet to every connected validator.
+    #[arg(long, env, default_value_t = LEADER_LOOKAHEAD_SLOTS)]
+    fanout_leader_lookahead_slots: u64,
+
+    /// Don't forward packets to the block engine.
+    #[arg(long, env, default_value_t = false)]
+    disable_mempool: bool,
+
+    #[arg(long, env)]
+    block_engine_url: Option<String>,
+
+    /// Defaults to --block-engine-url.
+    #[arg(long, env)]
+    block_engine_auth_service_url: Option<String>,
+
+    /// PEM CA bundle used to verify the block engine's TLS certificate.
+    #[arg(long, env)]
+    block_engine_tls_ca_path: Option<PathBuf>,
+
+    /// PEM client certificate presented to the block engine for mTLS.
+    #[arg(long, env, requires = "block_engine_tls_key_path")]
+    block_engine_tls_cert_path: Option<PathBuf>,
+
+    #[arg(long, env, requires = "block_engine_tls_cert_path")]
+    block_engine_tls_key_path: Option<PathBuf>,
+
+    /// How long delayed packets keep being released to validators after a
+    /// shutdown signal.
+    #[arg(long, env, default_value_t = 1_000)]
+    shutdown_drain_ms: u64,
+
+    /// Address to serve Prometheus metrics at /metrics and health at
+    /// /health on; disabled if unset.
+    #[arg(long, env)]
+    http_bind_addr: Option<SocketAddr>,
+
+    /// Record every packet batch the forwarder receives to this file, for
+    /// reproducing traffic with the replay binary; disabled if unset.
+    #[arg(long, env)]
+    capture_path: Option<PathBuf>,
+
+    /// Stop capturing once the capture file would grow past this many
+    /// bytes; 0 means no limit.
+    #[arg(long, env, default_value_t = DEFAULT_CAPTURE_MAX_BYTES)]
+    capture_max_bytes: u64,
+}
+
+fn get_tpu_sockets(args: &Args) -> TpuSockets {
+    let (tpu_quic_bind_port, transactions_quic_sockets) = multi_bind_in_range(
+        IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+        (
+            args.tpu_quic_port,
+            args.tpu_quic_port + args.num_tpu_quic_servers,
+        ),
+        args.num_tpu_quic_servers as usize,
+    )
+    .expect("Failed to bind TPU QUIC sockets");
+    assert_eq!(tpu_quic_bind_port, args.tpu_quic_port);
+
+    TpuSockets {
+        transactions_quic_sockets,
+    }
+}
+
+fn read_pem(path: &PathBuf) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()))
+}
+
+fn server_tls_config(args: &Args) -> Option<ServerTlsConfig> {
+    let (cert, key) = (args.tls_cert_path.as_ref()?, args.tls_key_path.as_ref()?);
+    let mut tls =
+        ServerTlsConfig::new().identity(Identity::from_pem(read_pem(cert), read_pem(key)));
+    if let Some(client_ca) = &args.tls_client_ca_path {
+        tls = tls.client_ca_root(Certificate::from_pem(read_pem(client_ca)));
+    }
+    Some(tls)
+}
+
+fn engine_tls_config(args: &Args) -> Option<ClientTlsConfig> {
+    if args.block_engine_tls_ca_path.is_none() && args.block_engine_tls_cert_path.is_none() {
+        return None;
+    }
+    let mut tls = ClientTlsConfig::new();
+    if let Some(ca) = &args.block_engine_tls_ca_path {
+        tls = tls.ca_certificate(Certificate::from_pem(read_pem(ca)));
+    }
+    if let (Some(cert), Some(key)) = (
+        &args.block_engine_tls_cert_path,
+        &args.block_engine_tls_key_path,
+    ) {
+        tls = tls.identity(Identity::from_pem(read_pem(cert), read_pem(key)));
+    }
+    Some(tls)
+}
+
+fn build_delay_policy(
+    args: &Args,
+    leader_cache: LeaderScheduleUpdatingHandle,
+    rpc_load_balancer: &Arc<LoadBalancer>,
+) -> Arc<dyn DelayPolicy> {
+    let packet_delay = Duration::from_millis(args.packet_delay_ms as u64);
+    let mut policy: Arc<dyn DelayPolicy> = match args.delay_policy {
+        DelayPolicyKind::Fixed => Arc::new(FixedDelay(packet_delay)),
+        DelayPolicyKind::LeaderProximity => {
+            let proximity = ScheduleProximity::new(
+                leader_cache,
+                rpc_load_balancer.clone(),
+                args.leader_proximity_validators.iter().copied().collect(),
+                args.leader_proximity_lookahead_slots,
+            );
+            Arc::new(LeaderProximityDelay::new(
+                Arc::new(proximity),
+                Duration::from_millis(args.near_leader_packet_delay_ms as u64),
+                packet_delay,
+                args.leader_proximity_lookahead_slots,
+            ))
+        }
+    };
+    if !args.zero_delay_signers.is_empty() {
+        policy = Arc::new(AllowlistDelay::new(
+            args.zero_delay_signers.iter().copied().collect(),
+            policy,
+        ));
+    }
+    policy
+}
+
+/// Parses flags and environment variables, filling in anything they leave
+/// unset from the --config file.
+fn parse_args() -> Args {
+    let matches = Args::command().ignore_errors(true).get_matches();
+    let Some(config_path) = matches.get_one::<PathBuf>("config") else {
+        return Args::parse();
+    };
+    match config::file_args(config_path, &Args::command(), &matches) {
+        Ok(file_args) => Args::parse_from(env::args_os().chain(file_args)),
+        Err(e) => Args::command().error(ErrorKind::InvalidValue, e).exit(),
+    }
+}
+
+/// Re-reads the --config file for a SIGHUP reload.
+fn reparse_args(config_path: &Path) -> Result<Args, String> {
+    let matches = Args::command()
+        .ignore_errors(true)
+        .try_get_matches_from(env::args_os())
+        .map_err(|e| e.to_string())?;
+    let file_args = config::file_args(config_path, &Args::command(), &matches)?;
+    Args::try_parse_from(env::args_os().chain(file_args)).map_err(|e| e.to_string())
 }
 
 fn main() {
-    env_logger::Builder::from_env(Env::new().default_filter_or("info")).init();
-    let args: Args = Args::parse();
+    let args = parse_args();
+    let log_level = logging::init(args.log_level);
     info!("args: {:?}", args);
 
-    let public_ip = get_public_ip_addr().expect("Failed to determine public IP");
+    // Validators connect to the advertised address, so never fall back to
+    // the bind address, which is usually unspecified.
+    let public_ip = args.public_ip.unwrap_or_else(|| {
+        get_public_ip_addr().unwrap_or_else(|e| {
+            Args::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    format!("Failed to determine public IP ({e:?}); set --public-ip"),
+                )
+                .exit()
+        })
+    });
     info!("public ip: {:?}", public_ip);
 
     let keypair = Arc::new(read_keypair_file(&args.keypair_path).expect("Keypair file not found"));
     info!("Relayer started with pubkey: {}", keypair.pubkey());
 
     let exit = Arc::new(AtomicBool::new(false));
-    let rpc_load_balancer = Arc::new(LoadBalancer::new(&args.rpc_servers, &exit));
+    let tpu_exit = Arc::new(AtomicBool::new(false));
+    let forwarder_exit = Arc::new(AtomicBool::new(false));
+    if args.rpc_servers.len() != args.websocket_servers.len() {
+        Args::command()
+            .error(
+                ErrorKind::WrongNumberOfValues,
+                "--rpc-servers and --websocket-servers must have the same number of entries",
+            )
+            .exit();
+    }
+    let servers: Vec<(String, String)> = args
+        .rpc_servers
+        .iter()
+        .cloned()
+        .zip(args.websocket_servers.iter().cloned())
+        .collect();
+    let (rpc_load_balancer, slot_receiver) = LoadBalancer::new(&servers, &exit);
+    let rpc_load_balancer = Arc::new(rpc_load_balancer);
+
+    let (tpu, tpu_receiver) = Tpu::new(
+        get_tpu_sockets(&args),
+        &tpu_exit,
+        &keypair,
+        &rpc_load_balancer,
+    );
+    let (verified_sender, verified_receiver) = crossbeam_channel::bounded(Tpu::TPU_QUEUE_CAPACITY);
+    let tpu_adapter = start_tpu_adapter(tpu_receiver, verified_sender, &tpu_exit);
 
-    let health_manager = HealthManager::new(exit.clone());
+    let health_manager = HealthManager::new(
+        slot_receiver,
+        Duration::from_secs(args.missing_slot_unhealthy_secs),
+        exit.clone(),
+    );
     let leader_cache = LeaderScheduleCacheUpdater::new(&rpc_load_balancer, &exit);
-    let relayer_svc = RelayerImpl::new(exit.clone(), leader_cache.handle());
+
+    let delay_policy = Arc::new(SwitchableDelayPolicy::new(build_delay_policy(
+        &args,
+        leader_cache.handle(),
+        &rpc_load_balancer,
+    )));
+    let (delay_packet_sender, delay_packet_receiver) =
+        crossbeam_channel::bounded(Tpu::TPU_QUEUE_CAPACITY);
+    let (engine_sender, engine_receiver) =
+        tokio::sync::mpsc::channel(EngineRelayerHandler::ENGINE_PACKET_QUEUE_CAPACITY);
+    let prometheus = Arc::new(PrometheusMetricsSink::default());
+    let metrics_sink: Arc<dyn MetricsSink> = Arc::new(MetricsSinks(vec![
+        Arc::new(LogMetricsSink),
+        prometheus.clone(),
+    ]));
+
+    let packet_filter = args.filter_packets.then(|| {
+        Arc::new(PacketFilter::new(FilterConfig {
+            verify_signatures: !args.filter_skip_signature_verification,
+            program_allowlist: args.program_allowlist.iter().copied().collect(),
+            program_denylist: args.program_denylist.iter().copied().collect(),
+        }))
+    });
+    let forwarder_stats = Arc::new(ForwarderStats::default());
+    let forward_and_delay_threads = start_forward_and_delay_thread(
+        verified_receiver,
+        delay_packet_sender,
+        engine_sender,
+        delay_policy.clone(),
+        health_manager.handle(),
+        ForwarderConfig {
+            num_threads: args.forwarder_threads,
+            disable_mempool: args.disable_mempool,
+            queue_capacity: args.forwarder_queue_capacity,
+            drop_policy: args.forwarder_drop_policy,
+            dedup: (args.dedup_ttl_secs > 0).then_some(DedupConfig {
+                ttl_secs: args.dedup_ttl_secs,
+                capacity: args.dedup_capacity,
+            }),
+            filter: packet_filter.clone(),
+            drain_timeout: Duration::from_millis(args.shutdown_drain_ms),
+            capture_path: args.capture_path.clone(),
+            capture_max_bytes: args.capture_max_bytes,
+        },
+        &forwarder_stats,
+        metrics_sink.clone(),
+        &forwarder_exit,
+    )
+    .unwrap_or_else(|e| Args::command().error(ErrorKind::Io, e).exit());
+
+    let engine_config = args
+        .block_engine_url
+        .clone()
+        .map(|engine_url| EngineConfig {
+            auth_service_url: args
+                .block_engine_auth_service_url
+                .clone()
+                .unwrap_or_else(|| engine_url.clone()),
+            engine_url,
+            keypair: keypair.clone(),
+            tls: engine_tls_config(&args),
+        });
+    let engine_relayer_handler =
+        EngineRelayerHandler::new(engine_config, engine_receiver, metrics_sink, exit.clone());
+
+    let validator_auther = AllowlistAuther::new(ValidatorAccessConfig {
+        allowlist_path: args.validator_allowlist_path.clone(),
+        min_stake_lamports: args.min_validator_stake_lamports,
+        below_min_stake: args.below_min_stake,
+        below_min_stake_auths_per_minute: args.below_min_stake_auths_per_minute,
+    });
+    let validator_auth_refresh =
+        validator_auther.start_refresh(rpc_load_balancer.clone(), metrics_sink.clone(), &exit);
+
+    let fanout = Arc::new(PacketFanout::new(
+        FanoutConfig {
+            subscriber_queue_capacity: args.subscriber_queue_capacity,
+            leader_lookahead_slots: args.fanout_leader_lookahead_slots,
+        },
+        leader_cache.handle(),
+        rpc_load_balancer.clone(),
+    ));
+    let fanout_thread = fanout.start(delay_packet_receiver, metrics_sink.clone(), &exit);
+    let relayer_svc = FanoutRelayer::new(fanout, public_ip, args.tpu_quic_port, &exit);
+    let pipeline = Pipeline {
+        tpu,
+        tpu_exit,
+        tpu_adapter,
+        forward_and_delay_threads,
+        forwarder_exit,
+        fanout_thread,
+        engine_relayer_handler,
+        exit: exit.clone(),
+    };
 
     let rt = Builder::new_multi_thread().enable_all().build().unwrap();
+    if let Some(addr) = args.http_bind_addr {
+        let state = HttpState {
+            prometheus,
+            health_state: health_manager.handle(),
+        };
+        rt.spawn(http_server::serve(addr, state, wait_for_exit(exit.clone())));
+    }
+    let reloader = Reloader {
+        delay_policy: delay_policy.clone(),
+        leader_cache: leader_cache.handle(),
+        rpc_load_balancer: rpc_load_balancer.clone(),
+        packet_filter,
+        log_level,
+    };
+    rt.spawn(reload_on_sighup(args.config.clone(), reloader));
     rt.block_on(async {
-        let auth_svc = AuthServiceImpl::new(ValidatorAuther::default(), exit.clone());
-        let server_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 11226);
+        let (health_reporter, health_svc) = tonic_health::server::health_reporter();
+        tokio::spawn(report_grpc_health(
+            health_reporter,
+            health_manager.handle(),
+            exit.clone(),
+        ));
+        let auth_svc = AuthServiceImpl::new(validator_auther, exit.clone());
+        let server_addr = args.grpc_bind_addr;
+        let mut server = Server::builder();
+        if let Some(tls) = server_tls_config(&args) {
+            info!(
+                "Serving gRPC over TLS{}",
+                if args.tls_client_ca_path.is_some() {
+                    " with client authentication"
+                } else {
+                    ""
+                }
+            );
+            server = server.tls_config(tls).expect("Invalid TLS configuration");
+        }
         info!("Starting relayer at: {:?}", server_addr);
 
-        Server::builder()
+        server
+            .add_service(health_svc)
+            .add_service(RelayerServer::with_interceptor(
+                relayer_svc,
+                auth_svc.interceptor(),
+            ))
             .add_service(auth_svc.into_service())
-            .add_service(relayer_svc.into_service())
-            .serve_with_shutdown(server_addr, shutdown_signal(exit.clone()))
+            .serve_with_shutdown(server_addr, async {
+                shutdown_signal().await;
+                if tokio::task::spawn_blocking(move || pipeline.shutdown())
+                    .await
+                    .is_err()
+                {
+                    error!("Pipeline shutdown panicked");
+                }
+                info!("Stopping relayer gRPC server");
+            })
             .await
             .expect("Failed to serve relayer");
     });
+
+    exit.store(true, Ordering::Relaxed);
+    if leader_cache.join().is_err() {
+        error!("Leader schedule cache updater panicked");
+    }
+    if validator_auth_refresh.join().is_err() {
+        error!("Validator auth refresh thread panicked");
+    }
+    if health_manager.join().is_err() {
+        error!("Health manager panicked");
+    }
+    info!("Relayer shut down");
 }
 
-async fn shutdown_signal(exit: Arc<AtomicBool>) {
-    let ctrl_c = async {
-        signal::ctrl_c()
-            .await
-            .expect("Failed to install Ctrl+C handler");
+/// Copies the TPU's verified packets into relayer [`Packet`]s, keeping each
+/// packet's source address and whether it came from a staked node. Packets
+/// sigverify discarded are left out. Runs until the TPU closes its channel.
+fn start_tpu_adapter(
+    tpu_receiver: Receiver<BankingPacketBatch>,
+    verified_sender: Sender<PacketBatch>,
+    exit: &Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    spawn_supervised("tpu_adapter".into(), exit.clone(), move || {
+        for banking_batch in tpu_receiver.iter() {
+            let batch: PacketBatch = banking_batch
+                .0
+                .iter()
+                .flat_map(|batch| batch.iter())
+                .filter(|packet| !packet.meta().discard())
+                .filter_map(|packet| {
+                    Some(Packet::new(
+                        packet.data(..)?.to_vec(),
+                        packet.meta().socket_addr(),
+                        packet.meta().is_from_staked_node(),
+                    ))
+                })
+                .collect();
+            if batch.is_empty() {
+                continue;
+            }
+            if verified_sender.send(batch).is_err() {
+                warn!("Forwarder stopped, no longer reading TPU packets");
+                return;
+            }
+        }
+    })
+}
+
+/// Threads that move packets from the TPU to validators and the block
+/// engine, shut down in the order packets flow through them.
+struct Pipeline {
+    tpu: Tpu,
+    tpu_exit: Arc<AtomicBool>,
+    tpu_adapter: thread::JoinHandle<()>,
+    forward_and_delay_threads: Vec<thread::JoinHandle<()>>,
+    forwarder_exit: Arc<AtomicBool>,
+    fanout_thread: thread::JoinHandle<()>,
+    engine_relayer_handler: EngineRelayerHandler,
+    exit: Arc<AtomicBool>,
+}
+
+impl Pipeline {
+    /// Stops accepting TPU traffic, drains the delay queue to validators,
+    /// then closes the block engine streams.
+    fn shutdown(self) {
+        info!("Stopping TPU");
+        self.tpu_exit.store(true, Ordering::Relaxed);
+        if self.tpu.join().is_err() {
+            error!("TPU thread panicked");
+        }
+        if self.tpu_adapter.join().is_err() {
+            error!("TPU adapter thread panicked");
+        }
+
+        info!("Draining forwarder");
+        self.forwarder_exit.store(true, Ordering::Relaxed);
+        for t in self.forward_and_delay_threads {
+            if t.join().is_err() {
+                error!("Forwarder thread panicked");
+            }
+        }
+        if self.fanout_thread.join().is_err() {
+            error!("Packet fanout thread panicked");
+        }
+
+        info!("Closing block engine streams");
+        self.exit.store(true, Ordering::Relaxed);
+        self.engine_relayer_handler.join();
+    }
+}
+
+/// Completes on Ctrl+C or SIGTERM.
+async fn shutdown_signal() {
+    let mut sigterm =
+        signal::unix::signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+    let received = tokio::select! {
+        _ = signal::ctrl_c() => "SIGINT",
+        _ = sigterm.recv() => "SIGTERM",
     };
-    tokio::select! {
-        _ = ctrl_c => {},
+    warn!("{received} received, shutting down...");
+}
+
+/// Settings that can change while the relayer runs.
+struct Reloader {
+    delay_policy: Arc<SwitchableDelayPolicy>,
+    leader_cache: LeaderScheduleUpdatingHandle,
+    rpc_load_balancer: Arc<LoadBalancer>,
+    /// `None` when --filter-packets was off at startup.
+    packet_filter: Option<Arc<PacketFilter>>,
+    log_level: LogLevelHandle,
+}
+
+impl Reloader {
+    fn apply(&self, args: &Args) {
+        self.delay_policy.set(build_delay_policy(
+            args,
+            self.leader_cache.clone(),
+            &self.rpc_load_balancer,
+        ));
+        if let Some(packet_filter) = &self.packet_filter {
+            packet_filter.set_program_lists(
+                args.program_allowlist.iter().copied().collect(),
+                args.program_denylist.iter().copied().collect(),
+            );
+        }
+        self.log_level.set(args.log_level);
+    }
+}
+
+/// Re-reads the --config file on every SIGHUP and applies the reloadable
+/// settings; anything else in it takes effect on the next restart. The
+/// validator allowlist file is reloaded on its own whenever it changes.
+async fn reload_on_sighup(config_path: Option<PathBuf>, reloader: Reloader) {
+    let mut sighup =
+        signal::unix::signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+    while sighup.recv().await.is_some() {
+        let Some(config_path) = &config_path else {
+            warn!("SIGHUP received without --config, nothing to reload");
+            continue;
+        };
+        match reparse_args(config_path) {
+            Ok(args) => {
+                reloader.apply(&args);
+                info!(
+                    "Reloaded {}: delay_policy={:?} packet_delay_ms={} program_allowlist={} \
+                     program_denylist={} log_level={}",
+                    config_path.display(),
+                    args.delay_policy,
+                    args.packet_delay_ms,
+                    args.program_allowlist.len(),
+                    args.program_denylist.len(),
+                    args.log_level
+                );
+            }
+            Err(e) => error!(
+                "Failed to reload {}, keeping current settings: {e}",
+                config_path.display()
+            ),
+        }
+    }
+}
+
+/// Mirrors the health manager's state into the gRPC health service.
+async fn report_grpc_health(
+    mut health_reporter: HealthReporter,
+    health_state: Arc<RwLock<HealthState>>,
+    exit: Arc<AtomicBool>,
+) {
+    let mut last = None;
+    while !exit.load(Ordering::Relaxed) {
+        let status = match *health_state.read().unwrap() {
+            HealthState::Healthy => ServingStatus::Serving,
+            HealthState::Unhealthy => ServingStatus::NotServing,
+        };
+        if last != Some(status) {
+            health_reporter.set_service_status("", status).await;
+            last = Some(status);
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+    health_reporter
+        .set_service_status("", ServingStatus::NotServing)
+        .await;
+}
+
+async fn wait_for_exit(exit: Arc<AtomicBool>) {
+    while !exit.load(Ordering::Relaxed) {
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(extra: &[&str]) -> Result<Args, clap::Error> {
+        let args = ["relayer", "--keypair-path", "relayer-keypair.json"];
+        Args::try_parse_from(args.iter().chain(extra))
+    }
+
+    #[test]
+    fn forwarder_queue_capacity_must_be_positive() {
+        assert!(parse(&["--forwarder-queue-capacity", "0"]).is_err());
+        let args = parse(&["--forwarder-queue-capacity", "1"]).unwrap();
+        assert_eq!(args.forwarder_queue_capacity, 1);
+    }
+
+    #[test]
+    fn subscriber_queue_capacity_must_be_positive() {
+        assert!(parse(&["--subscriber-queue-capacity", "0"]).is_err());
+    }
+
+    #[test]
+    fn leader_routing_is_on_by_default() {
+        assert_eq!(parse(&[]).unwrap().fanout_leader_lookahead_slots, 2);
+        let args = parse(&["--fanout-leader-lookahead-slots", "0"]).unwrap();
+        assert_eq!(args.fanout_leader_lookahead_slots, 0);
     }
-    exit.store(true, Ordering::Relaxed);
-    warn!("Signal received, shutting down...");
 }
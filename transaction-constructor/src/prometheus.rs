@@ -0,0 +1,152 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex};
+
+use blocks::metrics::{HistogramSnapshot, MetricsSink, LATENCY_BUCKETS_US};
+
+const METRIC_PREFIX: &str = "relayer";
+
+enum Metric {
+    Counter(u64),
+    Gauge(u64),
+    Histogram(HistogramSnapshot),
+}
+
+/// Accumulates interval submissions into cumulative Prometheus metrics and
+/// renders them in the text exposition format.
+///
+/// Counters are exported as `relayer_<name>_<field>_total`, so per-interval
+/// rates such as TPU packets received come from `rate()`. Histograms keep
+/// their microsecond bucket bounds.
+#[derive(Default)]
+pub struct PrometheusMetricsSink {
+    metrics: Mutex<BTreeMap<String, Metric>>,
+}
+
+impl PrometheusMetricsSink {
+    pub fn render(&self) -> String {
+        let metrics = self.metrics.lock().unwrap();
+        let mut out = String::new();
+        for (name, metric) in metrics.iter() {
+            match metric {
+                Metric::Counter(value) => {
+                    let _ = writeln!(out, "# TYPE {name} counter\n{name} {value}");
+                }
+                Metric::Gauge(value) => {
+                    let _ = writeln!(out, "# TYPE {name} gauge\n{name} {value}");
+                }
+                Metric::Histogram(histogram) => {
+                    let _ = writeln!(out, "# TYPE {name} histogram");
+                    let mut cumulative = 0;
+                    for (bound, count) in LATENCY_BUCKETS_US.iter().zip(&histogram.buckets) {
+                        cumulative += count;
+                        let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+                    }
+                    // Taken from the buckets, overflow included, rather than
+                    // `count`, which is swapped separately and may disagree.
+                    let total: u64 = histogram.buckets.iter().sum();
+                    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {total}");
+                    let _ = writeln!(out, "{name}_sum {}", histogram.sum_us);
+                    let _ = writeln!(out, "{name}_count {total}");
+                }
+            }
+        }
+        out
+    }
+}
+
+impl MetricsSink for PrometheusMetricsSink {
+    fn submit(&self, name: &'static str, points: &[(&'static str, u64)]) {
+        let mut metrics = self.metrics.lock().unwrap();
+        for (field, value) in points {
+            let metric = metrics
+                .entry(format!("{METRIC_PREFIX}_{name}_{field}_total"))
+                .or_insert(Metric::Counter(0));
+            if let Metric::Counter(total) = metric {
+                *total = total.saturating_add(*value);
+            }
+        }
+    }
+
+    fn submit_gauges(&self, name: &'static str, points: &[(&'static str, u64)]) {
+        let mut metrics = self.metrics.lock().unwrap();
+        for (field, value) in points {
+            metrics.insert(
+                format!("{METRIC_PREFIX}_{name}_{field}"),
+                Metric::Gauge(*value),
+            );
+        }
+    }
+
+    fn submit_histogram(
+        &self,
+        name: &'static str,
+        field: &'static str,
+        histogram: &HistogramSnapshot,
+    ) {
+        let mut metrics = self.metrics.lock().unwrap();
+        let metric = metrics
+            .entry(format!("{METRIC_PREFIX}_{name}_{field}"))
+            .or_insert_with(|| Metric::Histogram(HistogramSnapshot::default()));
+        if let Metric::Histogram(total) = metric {
+            for (bucket, count) in total.buckets.iter_mut().zip(&histogram.buckets) {
+                *bucket += count;
+            }
+            total.count += histogram.count;
+            total.sum_us += histogram.sum_us;
+            total.max_us = total.max_us.max(histogram.max_us);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blocks::metrics::LatencyHistogram;
+
+    use super::*;
+
+    #[test]
+    fn accumulates_counters_and_replaces_gauges() {
+        let sink = PrometheusMetricsSink::default();
+        sink.submit("forwarder", &[("packets", 2)]);
+        sink.submit("forwarder", &[("packets", 3)]);
+        sink.submit_gauges("fanout", &[("connected_validators", 4)]);
+        sink.submit_gauges("fanout", &[("connected_validators", 1)]);
+
+        let rendered = sink.render();
+        assert!(rendered.contains("# TYPE relayer_forwarder_packets_total counter\n"));
+        assert!(rendered.contains("relayer_forwarder_packets_total 5\n"));
+        assert!(rendered.contains("# TYPE relayer_fanout_connected_validators gauge\n"));
+        assert!(rendered.contains("relayer_fanout_connected_validators 1\n"));
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative_and_include_overflow() {
+        let histogram = LatencyHistogram::default();
+        for value_us in [50, 400, 2_000_000] {
+            histogram.record(value_us);
+        }
+        let sink = PrometheusMetricsSink::default();
+        sink.submit_histogram("engine_stats", "heartbeat_elapsed_us", &histogram.take());
+
+        let rendered = sink.render();
+        let name = "relayer_engine_stats_heartbeat_elapsed_us";
+        assert!(rendered.contains(&format!("{name}_bucket{{le=\"100\"}} 1\n")));
+        assert!(rendered.contains(&format!("{name}_bucket{{le=\"500\"}} 2\n")));
+        assert!(rendered.contains(&format!("{name}_bucket{{le=\"1000000\"}} 2\n")));
+        assert!(rendered.contains(&format!("{name}_bucket{{le=\"+Inf\"}} 3\n")));
+        assert!(rendered.contains(&format!("{name}_sum 2000450\n")));
+        assert!(rendered.contains(&format!("{name}_count 3\n")));
+    }
+
+    #[test]
+    fn inf_bucket_matches_buckets_when_count_disagrees() {
+        let mut snapshot = HistogramSnapshot::default();
+        snapshot.buckets[0] = 2;
+        snapshot.count = 3;
+        let sink = PrometheusMetricsSink::default();
+        sink.submit_histogram("engine_stats", "flush_elapsed_us", &snapshot);
+
+        let rendered = sink.render();
+        assert!(rendered.contains("relayer_engine_stats_flush_elapsed_us_bucket{le=\"+Inf\"} 2\n"));
+        assert!(rendered.contains("relayer_engine_stats_flush_elapsed_us_count 2\n"));
+    }
+}
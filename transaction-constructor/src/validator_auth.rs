@@ -0,0 +1,381 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+use blocks::{metrics::MetricsSink, supervisor::spawn_supervised};
+use builder_block::{auth::ValidatorAuther, rpc::LoadBalancer};
+use log::{error, info, warn};
+use solana_sdk::pubkey::Pubkey;
+
+const REFRESH_TICK: Duration = Duration::from_secs(1);
+const ALLOWLIST_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const STAKE_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// What happens to a validator below the minimum stake.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StakePolicy {
+    /// Refuse every connection.
+    #[default]
+    Reject,
+    /// Allow a few connections per minute.
+    RateLimit,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ValidatorAccessConfig {
+    /// File of allowed validator identities, one pubkey per line; `None`
+    /// allows any validator.
+    pub allowlist_path: Option<PathBuf>,
+    /// Minimum activated stake; 0 disables the stake check.
+    pub min_stake_lamports: u64,
+    pub below_min_stake: StakePolicy,
+    /// Connections allowed per validator per minute under
+    /// [`StakePolicy::RateLimit`].
+    pub below_min_stake_auths_per_minute: u32,
+}
+
+#[derive(Default)]
+pub struct ValidatorAuthStats {
+    pub accepted: AtomicU64,
+    pub rejected_not_allowlisted: AtomicU64,
+    pub rejected_low_stake: AtomicU64,
+    pub rate_limited: AtomicU64,
+}
+
+impl ValidatorAuthStats {
+    pub fn report(&self, sink: &dyn MetricsSink) {
+        sink.submit(
+            "validator_auth_stats",
+            &[
+                ("accepted", self.accepted.swap(0, Ordering::Relaxed)),
+                (
+                    "rejected_not_allowlisted",
+                    self.rejected_not_allowlisted.swap(0, Ordering::Relaxed),
+                ),
+                (
+                    "rejected_low_stake",
+                    self.rejected_low_stake.swap(0, Ordering::Relaxed),
+                ),
+                ("rate_limited", self.rate_limited.swap(0, Ordering::Relaxed)),
+            ],
+        );
+    }
+}
+
+/// Admits validators by identity allowlist and activated stake, and writes
+/// an audit log line for every attempt.
+///
+/// The allowlist file is reloaded when it changes and stakes are refreshed
+/// from RPC every minute by the thread [`AllowlistAuther::start_refresh`]
+/// returns. Until the first stake fetch succeeds the stake check is skipped,
+/// so a slow or failing RPC node doesn't lock every validator out.
+#[derive(Clone)]
+pub struct AllowlistAuther {
+    config: ValidatorAccessConfig,
+    allowlist: Arc<RwLock<Option<HashSet<Pubkey>>>>,
+    /// Activated stake per validator identity; `None` until first fetched.
+    stakes: Arc<RwLock<Option<HashMap<Pubkey, u64>>>>,
+    /// Start of the current rate limit window and the connections admitted
+    /// in it, per validator.
+    recent_auths: Arc<Mutex<HashMap<Pubkey, (Instant, u32)>>>,
+    stats: Arc<ValidatorAuthStats>,
+}
+
+impl AllowlistAuther {
+    pub fn new(config: ValidatorAccessConfig) -> Self {
+        let allowlist = config.allowlist_path.as_ref().map(|path| {
+            load_allowlist(path).unwrap_or_else(|e| {
+                panic!("Failed to load validator allowlist {}: {e}", path.display())
+            })
+        });
+        if let Some(allowlist) = &allowlist {
+            info!("Loaded {} allowlisted validators", allowlist.len());
+        }
+        AllowlistAuther {
+            config,
+            allowlist: Arc::new(RwLock::new(allowlist)),
+            stakes: Arc::new(RwLock::new(None)),
+            recent_auths: Arc::new(Mutex::new(HashMap::new())),
+            stats: Arc::new(ValidatorAuthStats::default()),
+        }
+    }
+
+    /// Spawns the thread that hot-reloads the allowlist, refreshes stakes
+    /// through `rpc_load_balancer` and reports auth stats.
+    pub fn start_refresh(
+        &self,
+        rpc_load_balancer: Arc<LoadBalancer>,
+        metrics_sink: Arc<dyn MetricsSink>,
+        exit: &Arc<AtomicBool>,
+    ) -> JoinHandle<()> {
+        let auther = self.clone();
+        let exit = exit.clone();
+        spawn_supervised("validator_auth_refresh".into(), exit.clone(), move || {
+            let mut allowlist_modified = auther
+                .config
+                .allowlist_path
+                .as_deref()
+                .and_then(modified_time);
+            let mut last_allowlist_check = Instant::now();
+            let mut last_stake_refresh: Option<Instant> = None;
+
+            while !exit.load(Ordering::Relaxed) {
+                if last_allowlist_check.elapsed() >= ALLOWLIST_CHECK_INTERVAL {
+                    auther.maybe_reload_allowlist(&mut allowlist_modified);
+                    last_allowlist_check = Instant::now();
+                }
+                let stakes_stale = match last_stake_refresh {
+                    Some(refreshed) => refreshed.elapsed() >= STAKE_REFRESH_INTERVAL,
+                    None => true,
+                };
+                // A failed refresh is retried on the next tick.
+                if auther.config.min_stake_lamports > 0
+                    && stakes_stale
+                    && auther.refresh_stakes(&rpc_load_balancer)
+                {
+                    last_stake_refresh = Some(Instant::now());
+                }
+                auther.stats.report(metrics_sink.as_ref());
+                thread::sleep(REFRESH_TICK);
+            }
+        })
+    }
+
+    fn maybe_reload_allowlist(&self, last_modified: &mut Option<SystemTime>) {
+        let Some(path) = &self.config.allowlist_path else {
+            return;
+        };
+        let modified = modified_time(path);
+        if modified == *last_modified {
+            return;
+        }
+        match load_allowlist(path) {
+            Ok(allowlist) => {
+                info!(
+                    "Reloaded {} allowlisted validators from {}",
+                    allowlist.len(),
+                    path.display()
+                );
+                *self.allowlist.write().unwrap() = Some(allowlist);
+                *last_modified = modified;
+            }
+            Err(e) => error!(
+                "Failed to reload validator allowlist {}, keeping the previous one: {e}",
+                path.display()
+            ),
+        }
+    }
+
+    /// Returns whether the stakes were refreshed.
+    fn refresh_stakes(&self, rpc_load_balancer: &LoadBalancer) -> bool {
+        match rpc_load_balancer.rpc_client().get_vote_accounts() {
+            Ok(vote_accounts) => {
+                let mut stakes = HashMap::new();
+                for account in vote_accounts
+                    .current
+                    .iter()
+                    .chain(vote_accounts.delinquent.iter())
+                {
+                    if let Ok(node) = Pubkey::from_str(&account.node_pubkey) {
+                        *stakes.entry(node).or_insert(0) += account.activated_stake;
+                    }
+                }
+                *self.stakes.write().unwrap() = Some(stakes);
+                true
+            }
+            Err(e) => {
+                warn!("Failed to refresh validator stakes, keeping previous: {e}");
+                false
+            }
+        }
+    }
+
+    fn check(&self, pubkey: &Pubkey) -> Result<(), String> {
+        if let Some(allowlist) = &*self.allowlist.read().unwrap() {
+            if !allowlist.contains(pubkey) {
+                self.stats
+                    .rejected_not_allowlisted
+                    .fetch_add(1, Ordering::Relaxed);
+                return Err("not allowlisted".to_string());
+            }
+        }
+
+        let min_stake = self.config.min_stake_lamports;
+        if min_stake == 0 {
+            return Ok(());
+        }
+        let Some(stake) = self
+            .stakes
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|stakes| stakes.get(pubkey).copied().unwrap_or_default())
+        else {
+            warn!("Validator stakes not yet known, skipping stake check for {pubkey}");
+            return Ok(());
+        };
+        if stake >= min_stake {
+            return Ok(());
+        }
+        match self.config.below_min_stake {
+            StakePolicy::Reject => {
+                self.stats
+                    .rejected_low_stake
+                    .fetch_add(1, Ordering::Relaxed);
+                Err(format!("stake {stake} below minimum {min_stake}"))
+            }
+            StakePolicy::RateLimit => {
+                let mut recent_auths = self.recent_auths.lock().unwrap();
+                let now = Instant::now();
+                let (window_start, count) = recent_auths.entry(*pubkey).or_insert((now, 0));
+                if now.duration_since(*window_start) >= RATE_LIMIT_WINDOW {
+                    *window_start = now;
+                    *count = 0;
+                }
+                if *count >= self.config.below_min_stake_auths_per_minute {
+                    self.stats.rate_limited.fetch_add(1, Ordering::Relaxed);
+                    return Err(format!(
+                        "rate limited, stake {stake} below minimum {min_stake}"
+                    ));
+                }
+                *count += 1;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ValidatorAuther for AllowlistAuther {
+    fn is_authorized(&self, pubkey: &Pubkey) -> bool {
+        match self.check(pubkey) {
+            Ok(()) => {
+                self.stats.accepted.fetch_add(1, Ordering::Relaxed);
+                info!(target: "audit", "validator auth accepted: {pubkey}");
+                true
+            }
+            Err(reason) => {
+                warn!(target: "audit", "validator auth rejected: {pubkey} ({reason})");
+                false
+            }
+        }
+    }
+}
+
+/// Reads one pubkey per line, ignoring blank lines and `#` comments.
+fn load_allowlist(path: &Path) -> Result<HashSet<Pubkey>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut allowlist = HashSet::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let pubkey = Pubkey::from_str(line).map_err(|e| format!("line {}: {line}: {e}", i + 1))?;
+        allowlist.insert(pubkey);
+    }
+    Ok(allowlist)
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn auther(below_min_stake: StakePolicy, stakes: &[(Pubkey, u64)]) -> AllowlistAuther {
+        let auther = AllowlistAuther::new(ValidatorAccessConfig {
+            min_stake_lamports: 100,
+            below_min_stake,
+            below_min_stake_auths_per_minute: 2,
+            ..ValidatorAccessConfig::default()
+        });
+        *auther.stakes.write().unwrap() = Some(stakes.iter().copied().collect());
+        auther
+    }
+
+    #[test]
+    fn load_allowlist_skips_comments_and_blank_lines() {
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let path = env::temp_dir().join(format!("allowlist-{}-valid", process::id()));
+        fs::write(
+            &path,
+            format!("# validators\n{first}\n\n  {second}  # backup\n"),
+        )
+        .unwrap();
+
+        let allowlist = load_allowlist(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(allowlist, HashSet::from([first, second]));
+    }
+
+    #[test]
+    fn load_allowlist_reports_the_invalid_line() {
+        let path = env::temp_dir().join(format!("allowlist-{}-invalid", process::id()));
+        fs::write(&path, format!("{}\nnot-a-pubkey\n", Pubkey::new_unique())).unwrap();
+
+        let error = load_allowlist(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(error.starts_with("line 2: not-a-pubkey"), "{error}");
+    }
+
+    #[test]
+    fn rate_limits_validators_below_the_minimum_stake() {
+        let (low, high) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let auther = auther(StakePolicy::RateLimit, &[(low, 99), (high, 100)]);
+
+        assert!(auther.is_authorized(&low));
+        assert!(auther.is_authorized(&low));
+        assert!(!auther.is_authorized(&low));
+        assert_eq!(auther.stats.rate_limited.load(Ordering::Relaxed), 1);
+        for _ in 0..3 {
+            assert!(auther.is_authorized(&high));
+        }
+    }
+
+    #[test]
+    fn rejects_validators_below_the_minimum_stake() {
+        let low = Pubkey::new_unique();
+        let auther = auther(StakePolicy::Reject, &[(low, 99)]);
+        assert!(!auther.is_authorized(&low));
+        assert!(!auther.is_authorized(&Pubkey::new_unique()));
+        assert_eq!(auther.stats.rejected_low_stake.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn skips_the_stake_check_until_stakes_are_known() {
+        let auther = auther(StakePolicy::Reject, &[]);
+        *auther.stakes.write().unwrap() = None;
+        assert!(auther.is_authorized(&Pubkey::new_unique()));
+    }
+
+    #[test]
+    fn allowlist_applies_before_stake() {
+        let (listed, unlisted) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let auther = auther(StakePolicy::Reject, &[(listed, 100), (unlisted, 100)]);
+        *auther.allowlist.write().unwrap() = Some(HashSet::from([listed]));
+
+        assert!(auther.is_authorized(&listed));
+        assert!(!auther.is_authorized(&unlisted));
+        assert_eq!(
+            auther
+                .stats
+                .rejected_not_allowlisted
+                .load(Ordering::Relaxed),
+            1
+        );
+    }
+}